@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+struct Inner {
+    file: File,
+    /// The last lines since [Capture::clear_tail].
+    tail: VecDeque<String>,
+    tail_lines: usize,
+}
+
+impl Inner {
+    fn line(&mut self, stderr: bool, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        let _ = writeln!(self.file, "{}| {}", if stderr { "err" } else { "out" }, line);
+        if self.tail_lines > 0 {
+            if self.tail.len() == self.tail_lines {
+                self.tail.pop_front();
+            }
+            self.tail.push_back(line.to_owned());
+        }
+    }
+}
+
+/// Copies the output of child processes to a session file while still showing it.
+#[derive(Clone)]
+pub struct Capture {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Capture {
+    /// Create a session file in `dir` and remove the oldest ones so at most `max_files` are kept.
+    pub fn open(dir: &Path, name: &str, tail_lines: usize, max_files: usize) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut old: Vec<_> = fs::read_dir(dir)?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "log"))
+            .collect();
+        old.sort();
+        if old.len() >= max_files.max(1) {
+            for p in &old[..old.len() + 1 - max_files.max(1)] {
+                let _ = fs::remove_file(p);
+            }
+        }
+        let file = File::create(dir.join(format!("{}.log", name)))?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                file,
+                tail: VecDeque::new(),
+                tail_lines,
+            })),
+        })
+    }
+
+    /// Write a header for the command `cml` started in `phase`.
+    pub fn begin(&self, phase: &str, cml: &[String]) {
+        let mut inner = self.inner.lock().unwrap();
+        let _ = writeln!(
+            inner.file,
+            "==== {} [{}] {:?}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            phase,
+            cml
+        );
+    }
+
+    /// Write the result of the last command.
+    pub fn end(&self, status: &str) {
+        let _ = writeln!(self.inner.lock().unwrap().file, "==== {}", status);
+    }
+
+    /// Forward `r` to stdout or stderr and the session file in a new thread.
+    ///
+    /// Progress lines ending with `\r` are shown as they are, but only the last one is written to the file.
+    pub fn tee<R: Read + Send + 'static>(&self, mut r: R, stderr: bool) -> JoinHandle<()> {
+        let inner = self.inner.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            let mut line = Vec::new();
+            // A `\r` not followed by `\n` starts the line again.
+            let mut cr = false;
+            loop {
+                let n = match r.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if stderr {
+                    let mut e = std::io::stderr();
+                    let _ = e.write_all(&buf[..n]);
+                    let _ = e.flush();
+                } else {
+                    let mut o = std::io::stdout();
+                    let _ = o.write_all(&buf[..n]);
+                    let _ = o.flush();
+                }
+                for b in &buf[..n] {
+                    match b {
+                        b'\n' => {
+                            inner.lock().unwrap().line(stderr, &line);
+                            line.clear();
+                            cr = false;
+                        }
+                        b'\r' => cr = true,
+                        _ => {
+                            if cr {
+                                line.clear();
+                                cr = false;
+                            }
+                            line.push(*b);
+                        }
+                    }
+                }
+            }
+            if !line.is_empty() {
+                inner.lock().unwrap().line(stderr, &line);
+            }
+        })
+    }
+
+    /// Forget the lines shown by [Self::tail], e.g. when a new phase starts.
+    pub fn clear_tail(&self) {
+        self.inner.lock().unwrap().tail.clear();
+    }
+
+    /// The last lines written since [Self::clear_tail].
+    pub fn tail(&self) -> Vec<String> {
+        self.inner.lock().unwrap().tail.iter().cloned().collect()
+    }
+}
+
+static CURRENT: Mutex<Option<Capture>> = Mutex::new(None);
+
+/// Start copying the output of commands in this session, see [Capture::open].
+pub fn init(dir: &Path, name: &str, tail_lines: usize, max_files: usize) -> std::io::Result<()> {
+    *CURRENT.lock().unwrap() = Some(Capture::open(dir, name, tail_lines, max_files)?);
+    Ok(())
+}
+
+/// The capture of this session, if enabled.
+pub fn current() -> Option<Capture> {
+    CURRENT.lock().unwrap().clone()
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+use yaml_rust::ScanError;
+
+/// A position in a config file.
+#[derive(Clone, Debug)]
+pub struct Pos {
+    pub file: Option<Arc<str>>,
+    pub mark: Marker,
+}
+
+impl std::fmt::Display for Pos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}, ", file)?;
+        }
+        write!(f, "line {}, column {}", self.mark.line(), self.mark.col() + 1)
+    }
+}
+
+/// Positions of keys and values in the first YAML document, by dotted path like `games.foo.game_exe`.
+#[derive(Clone, Debug, Default)]
+pub struct Positions {
+    keys: HashMap<String, Pos>,
+    values: HashMap<String, Pos>,
+}
+
+impl Positions {
+    pub fn load(s: &str, file: &Path) -> Result<Self, ScanError> {
+        let mut r = Recorder {
+            file: Some(Arc::from(file.to_string_lossy().as_ref())),
+            ..Default::default()
+        };
+        Parser::new(s.chars()).load(&mut r, false)?;
+        Ok(r.pos)
+    }
+
+    pub fn key(&self, path: &str) -> Option<Pos> {
+        self.keys.get(path).cloned()
+    }
+
+    /// The position of a value, or of its key if unknown.
+    pub fn value(&self, path: &str) -> Option<Pos> {
+        self.values.get(path).or(self.keys.get(path)).cloned()
+    }
+
+    /// Add positions from a file merged on top of this one.
+    pub fn merge(&mut self, other: Positions) {
+        self.keys.extend(other.keys);
+        self.values.extend(other.values);
+    }
+
+    /// Make everything under `prefix` also available without the prefix.
+    pub fn overlay(&mut self, prefix: &str) {
+        let prefix = format!("{}.", prefix);
+        for map in [&mut self.keys, &mut self.values] {
+            let found: Vec<_> = map
+                .iter()
+                .filter_map(|(k, v)| k.strip_prefix(&prefix).map(|k| (k.to_owned(), v.clone())))
+                .collect();
+            map.extend(found);
+        }
+    }
+}
+
+pub fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+enum FrameKind {
+    Map(Option<String>),
+    Seq(usize),
+}
+
+struct Frame {
+    path: String,
+    kind: FrameKind,
+    /// A map or sequence used as a key.
+    is_key: bool,
+}
+
+#[derive(Default)]
+struct Recorder {
+    file: Option<Arc<str>>,
+    stack: Vec<Frame>,
+    pos: Positions,
+    done: bool,
+}
+
+impl Recorder {
+    fn value_path(&self) -> String {
+        match self.stack.last() {
+            None => String::new(),
+            Some(f) => match &f.kind {
+                FrameKind::Map(key) => join(&f.path, key.as_deref().unwrap_or("?")),
+                FrameKind::Seq(i) => join(&f.path, &i.to_string()),
+            },
+        }
+    }
+
+    /// Move to the next key or item after a value is finished.
+    fn advance(&mut self) {
+        if let Some(f) = self.stack.last_mut() {
+            match &mut f.kind {
+                FrameKind::Map(key) => *key = None,
+                FrameKind::Seq(i) => *i += 1,
+            }
+        }
+    }
+}
+
+impl MarkedEventReceiver for Recorder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        if self.done {
+            return;
+        }
+        match ev {
+            Event::DocumentEnd => self.done = true,
+            Event::MappingEnd | Event::SequenceEnd => {
+                if let Some(f) = self.stack.pop() {
+                    if !f.is_key {
+                        self.advance();
+                    }
+                }
+            }
+            Event::Scalar(..) | Event::Alias(_) | Event::MappingStart(_) | Event::SequenceStart(_) => {
+                let mut is_key = false;
+                if let Some(Frame {
+                    path,
+                    kind: FrameKind::Map(key @ None),
+                    ..
+                }) = self.stack.last_mut()
+                {
+                    let k = match &ev {
+                        Event::Scalar(s, ..) => s.clone(),
+                        _ => String::from("?"),
+                    };
+                    let pos = Pos {
+                        file: self.file.clone(),
+                        mark,
+                    };
+                    // A block map is marked at the colon after its first key, so use the key instead.
+                    if let Some(v) = self.pos.values.get_mut(path.as_str()) {
+                        if v.mark.index() > mark.index() {
+                            v.mark = mark;
+                        }
+                    }
+                    self.pos.keys.insert(join(path, &k), pos);
+                    *key = Some(k);
+                    is_key = true;
+                }
+                let path = self.value_path();
+                if !is_key {
+                    let pos = Pos {
+                        file: self.file.clone(),
+                        mark,
+                    };
+                    self.pos.values.insert(path.clone(), pos);
+                }
+                match ev {
+                    Event::MappingStart(_) => self.stack.push(Frame {
+                        path,
+                        kind: FrameKind::Map(None),
+                        is_key,
+                    }),
+                    Event::SequenceStart(_) => self.stack.push(Frame {
+                        path,
+                        kind: FrameKind::Seq(0),
+                        is_key,
+                    }),
+                    _ => {
+                        if !is_key {
+                            self.advance();
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
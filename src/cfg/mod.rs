@@ -0,0 +1,832 @@
+mod marked;
+mod schema;
+mod settings;
+
+use crate::process::Matcher;
+use crate::snapshot::retention::Policy;
+use crate::utils::FailurePolicy;
+use marked::Positions;
+use schema::Issue;
+use settings::Settings;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::fs::File;
+use std::io::Read;
+use yaml_rust::{yaml::Hash, ScanError, Yaml, YamlLoader};
+
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum ConfigError {
+    Yaml(ScanError),
+    IO(std::io::Error),
+    #[display(fmt = "Invalid config file:\n{}", _0)]
+    #[from(ignore)]
+    Invalid(String),
+    #[display(fmt = "Failed to read {}: {}", _0, _1)]
+    #[from(ignore)]
+    Read(String, std::io::Error),
+    #[display(fmt = "Include cycle detected: {}", _0)]
+    #[from(ignore)]
+    IncludeCycle(String),
+    #[display(fmt = "Game profile {} not found.", _0)]
+    #[from(ignore)]
+    UnknownGame(String),
+    #[display(fmt = "Please select a game profile with --game: {}", _0)]
+    #[from(ignore)]
+    GameRequired(String),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackupEngine {
+    /// Call game-backuper or `backup_command`/`restore_command`.
+    #[default]
+    External,
+    /// Copy `backup_paths` into snapshots under `snapshot_dir`.
+    Native,
+}
+
+/// A process to wait for after the game command returns, e.g. the game started by a launcher.
+#[derive(Debug)]
+pub struct WaitForProcess {
+    pub matcher: Matcher,
+    /// How long the process may take to appear.
+    pub appear_timeout: Duration,
+    /// How long the process may run, unlimited if not set.
+    pub timeout: Option<Duration>,
+}
+
+/// How failed commands are retried.
+#[derive(Debug, Default)]
+pub struct Retry {
+    /// Retries after the first attempt.
+    pub attempts: usize,
+    /// The delay before the first retry, doubled for each one after it.
+    pub delay: Duration,
+    pub max_delay: Duration,
+    /// Exit codes which are retried, any non-zero exit code if empty.
+    pub exit_codes: Vec<u32>,
+}
+
+/// A command run around a phase.
+#[derive(Debug)]
+pub struct Hook {
+    pub command: Vec<String>,
+    /// Whether a failure of the command aborts the run, `on_failure: abort`.
+    pub abort_on_failure: bool,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    obj: Hash,
+    /// Profiles from the `games` map. Top-level keys are shared defaults.
+    games: Option<Hash>,
+    pos: Positions,
+    /// The directory of the main config file.
+    config_dir: PathBuf,
+    /// The selected game profile, or the config file name without extension.
+    game: String,
+    /// The settings read from `obj`, updated when a game is selected.
+    settings: Settings,
+}
+
+fn to_duration(v: &Yaml) -> Option<Duration> {
+    match v {
+        Yaml::Integer(i) => Some(Duration::from_secs((*i).max(0) as u64)),
+        Yaml::String(s) => crate::utils::parse_duration(s),
+        _ => None,
+    }
+}
+
+fn issues_to_result(issues: Vec<Issue>) -> Result<(), ConfigError> {
+    if issues.is_empty() {
+        return Ok(());
+    }
+    let msg: Vec<_> = issues.iter().map(|i| format!("  {}", i)).collect();
+    Err(ConfigError::Invalid(msg.join("\n")))
+}
+
+/// Expand `~`, `$VAR`, `${VAR}` and the placeholders `{exe_dir}`, `{config_dir}`,
+/// `{game}` and `{hostname}`. Unknown variables and placeholders are kept as is.
+fn expand(s: &str, config_dir: &Path, game: &str) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+        if let Some(home) = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+            out.push_str(&home.to_string_lossy());
+            rest = &rest[1..];
+        }
+    }
+    while let Some(i) = rest.find(['$', '{']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let (name, len, is_env) = if let Some(r) = rest.strip_prefix("${") {
+            match r.find('}') {
+                Some(e) => (&r[..e], e + 3, true),
+                None => ("", 0, true),
+            }
+        } else if let Some(r) = rest.strip_prefix('$') {
+            let e = r
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(r.len());
+            (&r[..e], e + 1, true)
+        } else {
+            match rest.find('}') {
+                Some(e) => (&rest[1..e], e + 1, false),
+                None => ("", 0, false),
+            }
+        };
+        let value = if name.is_empty() {
+            None
+        } else if is_env {
+            std::env::var(name).ok()
+        } else {
+            match name {
+                "exe_dir" => Some(crate::utils::get_exe_path_else_current().to_string_lossy().to_string()),
+                "config_dir" => Some(config_dir.to_string_lossy().to_string()),
+                "game" => Some(game.to_owned()),
+                "hostname" => Some(crate::utils::hostname()),
+                _ => None,
+            }
+        };
+        match value {
+            Some(v) => {
+                out.push_str(&v);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Merge `over` into `base`. Nested maps are merged, other values are replaced.
+fn merge(base: &mut Hash, over: &Hash) {
+    for (k, v) in over.iter() {
+        match (base.get_mut(k), v) {
+            (Some(Yaml::Hash(b)), Yaml::Hash(o)) => merge(b, o),
+            _ => {
+                base.insert(k.clone(), v.clone());
+            }
+        }
+    }
+}
+
+fn load_file(path: &Path, stack: &mut Vec<PathBuf>) -> Result<(Hash, Positions), ConfigError> {
+    let read_err = |e| ConfigError::Read(path.to_string_lossy().to_string(), e);
+    let canon = path.canonicalize().map_err(read_err)?;
+    if stack.contains(&canon) {
+        let chain: Vec<_> = stack
+            .iter()
+            .chain(std::iter::once(&canon))
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        return Err(ConfigError::IncludeCycle(chain.join(" -> ")));
+    }
+    let mut s = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut s))
+        .map_err(read_err)?;
+    stack.push(canon);
+    let re = load_str(&s, path, stack);
+    stack.pop();
+    re
+}
+
+/// Parse and check one config file, then merge it on top of the files it includes.
+fn load_str(s: &str, path: &Path, stack: &mut Vec<PathBuf>) -> Result<(Hash, Positions), ConfigError> {
+    let re = YamlLoader::load_from_str(s)?;
+    let pos = Positions::load(s, path)?;
+    let mut obj = match re.into_iter().next() {
+        Some(Yaml::Hash(h)) => h,
+        _ => {
+            return Err(ConfigError::Invalid(format!(
+                "  {}: The config file should be a map.",
+                path.to_string_lossy()
+            )));
+        }
+    };
+    let mut issues = Vec::new();
+    schema::check_map(&obj, schema::TOP, "", &pos, &mut issues);
+    issues_to_result(issues)?;
+    let mut includes = Vec::new();
+    for k in ["include", "extends"] {
+        match obj.remove(&Yaml::from_str(k)) {
+            Some(Yaml::String(s)) => includes.push(s),
+            Some(Yaml::Array(a)) => includes.extend(a.iter().filter_map(|s| s.as_str().map(|s| s.to_owned()))),
+            _ => {}
+        }
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    // Profiles are not selected yet, so `{game}` is the name of the main config file here.
+    let main = stack[0].clone();
+    let config_dir = main.parent().unwrap_or(Path::new("."));
+    let game = main.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let mut base = Hash::new();
+    let mut base_pos = Positions::default();
+    for inc in includes {
+        let (h, p) = load_file(&dir.join(expand(&inc, config_dir, &game)), stack)?;
+        merge(&mut base, &h);
+        base_pos.merge(p);
+    }
+    merge(&mut base, &obj);
+    base_pos.merge(pos);
+    Ok((base, base_pos))
+}
+
+impl Config {
+    pub fn from_file_path<P: AsRef<Path> + ?Sized>(path: &P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let (mut obj, pos) = load_file(path, &mut Vec::new())?;
+        let games = match obj.remove(&Yaml::from_str("games")) {
+            Some(Yaml::Hash(h)) => Some(h),
+            _ => None,
+        };
+        let config_dir = path
+            .canonicalize()
+            .ok()
+            .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+            .unwrap_or_else(|| PathBuf::from("."));
+        let game = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut cfg = Self {
+            obj,
+            games,
+            pos,
+            config_dir,
+            game,
+            settings: Settings::default(),
+        };
+        cfg.settings = Settings::load(&cfg);
+        Ok(cfg)
+    }
+
+    /// Names of all game profiles.
+    pub fn games(&self) -> Vec<String> {
+        match &self.games {
+            Some(g) => g.keys().filter_map(|k| k.as_str().map(|s| s.to_owned())).collect(),
+            None => vec![],
+        }
+    }
+
+    /// Apply a game profile on top of the shared settings.
+    /// If `name` is `None`, the only profile is selected if there is exactly one.
+    pub fn select_game(&mut self, name: Option<&str>) -> Result<(), ConfigError> {
+        if let Some(games) = &self.games {
+            let name = match name {
+                Some(n) => n.to_owned(),
+                None => {
+                    let names = self.games();
+                    if names.len() != 1 {
+                        return Err(ConfigError::GameRequired(names.join(", ")));
+                    }
+                    names[0].clone()
+                }
+            };
+            match games.get(&Yaml::String(name.clone())) {
+                Some(Yaml::Hash(h)) => merge(&mut self.obj, h),
+                Some(_) => {}
+                None => return Err(ConfigError::UnknownGame(name)),
+            }
+            self.pos.overlay(&format!("games.{}", name));
+            self.game = name;
+        } else if let Some(n) = name {
+            return Err(ConfigError::UnknownGame(n.to_owned()));
+        }
+        self.settings = Settings::load(self);
+        self.check()
+    }
+
+    /// Check settings which depend on each other.
+    fn check(&self) -> Result<(), ConfigError> {
+        let mut issues = Vec::new();
+        let remote = self.rclone_remote().is_some();
+        if remote != self.rclone_local().is_some() {
+            let (set, unset) = if remote {
+                ("rclone_remote", "rclone_local")
+            } else {
+                ("rclone_local", "rclone_remote")
+            };
+            issues.push(Issue {
+                pos: self.pos.key(set),
+                msg: format!("{} need be set when {} is set", unset, set),
+            });
+        }
+        if self.rclone_history() && !remote {
+            issues.push(Issue {
+                pos: self.pos.key("rclone_history"),
+                msg: String::from("rclone_remote need be set when rclone_history is enabled"),
+            });
+        }
+        if self.backup_engine() == BackupEngine::Native && self.backup_paths().is_empty() {
+            issues.push(Issue {
+                pos: self.pos.key("backup_engine"),
+                msg: String::from("backup_paths need be set when backup_engine is native"),
+            });
+        }
+        if self.watch_saves() && self.watch_paths().is_empty() {
+            issues.push(Issue {
+                pos: self.pos.key("watch_saves"),
+                msg: String::from("watch_paths or backup_paths need be set when watch_saves is enabled"),
+            });
+        }
+        if let Some(Yaml::Integer(i)) = self.get("sync_max_delete") {
+            if *i > 100 {
+                issues.push(Issue {
+                    pos: self.pos.value("sync_max_delete"),
+                    msg: String::from("sync_max_delete is a percentage and can not be more than 100"),
+                });
+            }
+        }
+        if let Some(Yaml::Hash(w)) = self.get("wait_for_process") {
+            let set: Vec<_> = ["name", "path", "cmdline"]
+                .into_iter()
+                .filter(|k| w.get(&Yaml::from_str(k)).is_some_and(|v| !v.is_null()))
+                .collect();
+            if set.len() != 1 {
+                issues.push(Issue {
+                    pos: self.pos.key("wait_for_process"),
+                    msg: String::from("wait_for_process need exactly one of name, path and cmdline"),
+                });
+            }
+            if let Some(Yaml::String(r)) = w.get(&Yaml::from_str("cmdline")) {
+                if cfg!(windows) {
+                    issues.push(Issue {
+                        pos: self.pos.key("wait_for_process.cmdline"),
+                        msg: String::from("wait_for_process.cmdline is not supported on Windows"),
+                    });
+                } else if let Err(e) = regex::Regex::new(r) {
+                    issues.push(Issue {
+                        pos: self.pos.value("wait_for_process.cmdline"),
+                        msg: format!("wait_for_process.cmdline is not a valid regex: {}", e),
+                    });
+                }
+            }
+        }
+        issues_to_result(issues)
+    }
+
+    pub fn get<S: AsRef<str> + ?Sized>(&self, s: &S) -> Option<&Yaml> {
+        let k = Yaml::from_str(s.as_ref());
+        self.obj.get(&k)
+    }
+
+    pub fn get_bool<S: AsRef<str> + ?Sized>(&self, s: &S) -> Option<&bool> {
+        match self.get(s) {
+            Some(Yaml::Boolean(i)) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// Expand variables and placeholders in `s`, see [expand].
+    pub fn expand(&self, s: &str) -> String {
+        expand(s, &self.config_dir, &self.game)
+    }
+
+    pub fn get_str<S: AsRef<str> + ?Sized>(&self, s: &S) -> Option<String> {
+        match self.get(s) {
+            Some(Yaml::String(s)) => Some(self.expand(s)),
+            _ => None,
+        }
+    }
+
+    /// Get a duration given as seconds or a string like `20m`.
+    pub fn get_duration<S: AsRef<str> + ?Sized>(&self, s: &S) -> Option<Duration> {
+        self.get(s).and_then(to_duration)
+    }
+
+    pub fn get_str_vec<S: AsRef<str> + ?Sized>(&self, s: &S) -> Option<Vec<String>> {
+        self.get(s).and_then(|e| self.to_str_vec(e))
+    }
+
+    fn to_str_vec(&self, e: &Yaml) -> Option<Vec<String>> {
+        match e {
+            Yaml::String(s) => Some(vec![self.expand(s)]),
+            Yaml::Array(s) => Some(
+                s.iter()
+                    .filter_map(|s| match s {
+                        Yaml::String(s) => Some(self.expand(s)),
+                        Yaml::Integer(i) => Some(i.to_string()),
+                        Yaml::Real(i) => Some(i.to_owned()),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// The selected game profile, or the config file name without extension.
+    pub fn game(&self) -> &str {
+        &self.game
+    }
+
+    pub fn game_backuper_cfg(&self) -> Option<String> {
+        self.settings.game_backuper_cfg.clone()
+    }
+
+    pub fn game_backuper_exe(&self) -> String {
+        self.settings.game_backuper_exe.clone()
+    }
+
+    pub fn backup_engine(&self) -> BackupEngine {
+        self.settings.backup_engine
+    }
+
+    pub fn backup_paths(&self) -> Vec<String> {
+        self.settings.backup_paths.clone()
+    }
+
+    /// Defaults to `rclone_local`, so snapshots are uploaded by rclone.
+    pub fn snapshot_dir(&self) -> PathBuf {
+        self.settings.snapshot_dir.clone()
+    }
+
+    /// Store snapshots by content hash, so unchanged files are only saved once.
+    pub fn snapshot_dedup(&self) -> bool {
+        self.settings.snapshot_dedup
+    }
+
+    /// Labels added to new native snapshots.
+    pub fn snapshot_tags(&self) -> Vec<String> {
+        self.settings.snapshot_tags.clone()
+    }
+
+    /// Retention policy for native snapshots, `None` keeps everything.
+    pub fn retention(&self) -> Option<Policy> {
+        self.settings.retention.clone()
+    }
+
+    pub fn game_exe(&self) -> Option<Vec<String>> {
+        self.settings.game_exe.clone()
+    }
+
+    pub fn backup_command(&self) -> Option<Vec<String>> {
+        self.settings.backup_command.clone()
+    }
+
+    pub fn restore_command(&self) -> Option<Vec<String>> {
+        self.settings.restore_command.clone()
+    }
+
+    pub fn pause_at_exit(&self) -> bool {
+        self.settings.pause_at_exit
+    }
+
+    pub fn pause_on_backup_error(&self) -> bool {
+        self.settings.pause_on_backup_error
+    }
+
+    pub fn rclone_exe(&self) -> String {
+        self.settings.rclone_exe.clone()
+    }
+
+    pub fn rclone_remote(&self) -> Option<String> {
+        self.settings.rclone_remote.clone()
+    }
+
+    pub fn rclone_local(&self) -> Option<String> {
+        self.settings.rclone_local.clone()
+    }
+
+    pub fn rclone_flag(&self) -> Vec<String> {
+        self.settings.rclone_flag.clone()
+    }
+
+    /// Filter flags keeping files of this program out of syncs and listings,
+    /// for those which are inside `rclone_remote` like the lock and history of a root remote.
+    pub fn rclone_exclude(&self) -> Vec<String> {
+        let remote = match &self.settings.rclone_remote {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+        let mut re = Vec::new();
+        if let Some(rel) = self.settings.lock_path.as_deref().and_then(|l| remote_relative(remote, l)) {
+            re.push(String::from("--exclude"));
+            re.push(format!("/{}", escape_glob(&rel)));
+        }
+        if let Some(rel) = remote_relative(remote, &crate::history::base(remote)) {
+            re.push(String::from("--exclude"));
+            re.push(format!("/{}/**", escape_glob(&rel)));
+        }
+        re
+    }
+
+    /// Keep files replaced by uploads in a history folder next to `rclone_remote`.
+    pub fn rclone_history(&self) -> bool {
+        self.settings.rclone_history
+    }
+
+    /// How many history folders to keep when pruning.
+    pub fn rclone_history_keep(&self) -> Option<usize> {
+        self.settings.rclone_history_keep
+    }
+
+    /// Keep history folders newer than this when pruning.
+    pub fn rclone_history_max_age(&self) -> Option<Duration> {
+        self.settings.rclone_history_max_age
+    }
+
+    #[cfg(windows)]
+    pub fn hide_window_when_running_exe(&self) -> bool {
+        self.settings.hide_window_when_running_exe
+    }
+
+    /// Check whether local saves are newer than the remote ones before restoring from rclone.
+    pub fn conflict_check(&self) -> bool {
+        self.settings.conflict_check
+    }
+
+    /// Ask before rclone syncs from an empty source or deletes too many files.
+    pub fn sync_guard(&self) -> bool {
+        self.settings.sync_guard
+    }
+
+    /// The share of destination files in percent a sync may delete without asking.
+    pub fn sync_max_delete(&self) -> usize {
+        self.settings.sync_max_delete
+    }
+
+    /// Lock the remote while playing, so other machines do not play the same save.
+    pub fn session_lock(&self) -> bool {
+        self.settings.session_lock
+    }
+
+    /// Defaults to a `.lock` file next to `rclone_remote`.
+    pub fn lock_path(&self) -> Option<String> {
+        self.settings.lock_path.clone()
+    }
+
+    /// Locks older than this are considered stale.
+    pub fn lock_expire(&self) -> Duration {
+        self.settings.lock_expire
+    }
+
+    /// The failure policy of `phase` from `on_failure`, either the one for all phases or by phase name.
+    pub fn on_failure(&self, phase: &str) -> Option<FailurePolicy> {
+        self.settings
+            .on_failure
+            .or_else(|| self.settings.on_failure_phases.get(phase).copied())
+    }
+
+    /// Where state between sessions is kept, relative to the directory of the executable.
+    pub fn state_dir(&self) -> PathBuf {
+        self.settings.state_dir.clone()
+    }
+
+    /// Retries of rclone if `rclone` is set, or of the backup and restore commands.
+    pub fn retry(&self, rclone: bool) -> &Retry {
+        if rclone {
+            &self.settings.rclone_retry
+        } else {
+            &self.settings.retry
+        }
+    }
+
+    pub fn continue_when_run_failed(&self) -> bool {
+        self.settings.continue_when_run_failed
+    }
+
+    /// Wait until every process started by the game has exited.
+    #[cfg(target_os = "linux")]
+    pub fn track_process_tree(&self) -> bool {
+        self.settings.track_process_tree
+    }
+
+    pub fn wait_for_process(&self) -> Option<&WaitForProcess> {
+        self.settings.wait_for_process.as_ref()
+    }
+
+    /// Back up periodically while the game is running, disabled if not set.
+    pub fn autosave_interval(&self) -> Option<Duration> {
+        self.settings.autosave_interval
+    }
+
+    /// Back up shortly after the game stops writing to `watch_paths`.
+    pub fn watch_saves(&self) -> bool {
+        self.settings.watch_saves
+    }
+
+    /// Paths to watch, `backup_paths` by default.
+    pub fn watch_paths(&self) -> Vec<String> {
+        self.settings.watch_paths.clone()
+    }
+
+    /// How long the saves must stay unchanged before a backup is started.
+    pub fn watch_debounce(&self) -> Duration {
+        self.settings.watch_debounce
+    }
+
+    /// Also upload to the remote after each autosave.
+    pub fn autosave_upload(&self) -> bool {
+        self.settings.autosave_upload
+    }
+
+    /// The hook `name` in `hooks`, failures abort the run unless `on_failure` is `continue`.
+    pub fn hook(&self, name: &str) -> Option<&Hook> {
+        self.settings.hooks.get(name)
+    }
+
+    /// The log file, relative to the directory of the executable.
+    pub fn log_file(&self) -> PathBuf {
+        self.settings.log_file.clone()
+    }
+
+    /// Messages below this level are not written to the log file, `None` disables the log file.
+    pub fn log_level(&self) -> Option<crate::log::Level> {
+        self.settings.log_level
+    }
+
+    /// Rotate the log file when it would grow over this size in bytes, 0 means no limit.
+    pub fn log_max_size(&self) -> u64 {
+        self.settings.log_max_size
+    }
+
+    /// How many rotated log files are kept.
+    pub fn log_max_files(&self) -> usize {
+        self.settings.log_max_files
+    }
+
+    /// Copy the output of commands to a file for each session.
+    pub fn capture_output(&self) -> bool {
+        self.settings.capture_output
+    }
+
+    /// Where the output files are stored, relative to the directory of the executable.
+    pub fn capture_dir(&self) -> PathBuf {
+        self.settings.capture_dir.clone()
+    }
+
+    /// How many lines of output are shown when a phase failed.
+    pub fn capture_tail_lines(&self) -> usize {
+        self.settings.capture_tail_lines
+    }
+
+    /// How many output files are kept.
+    pub fn capture_max_files(&self) -> usize {
+        self.settings.capture_max_files
+    }
+
+    #[cfg(windows)]
+    pub fn hook_dll(&self) -> Vec<String> {
+        self.settings.hook_dll.clone()
+    }
+
+    #[cfg(windows)]
+    pub fn current_dir(&self) -> Option<String> {
+        self.settings.current_dir.clone()
+    }
+}
+
+/// The part of the rclone path `path` inside `remote`, if it is inside.
+fn remote_relative(remote: &str, path: &str) -> Option<String> {
+    let remote = remote.trim_end_matches('/');
+    let rel = if remote.ends_with(':') {
+        path.strip_prefix(remote)?
+    } else {
+        path.strip_prefix(remote)?.strip_prefix('/')?
+    };
+    let rel = rel.trim_matches('/');
+    if rel.is_empty() {
+        None
+    } else {
+        Some(rel.to_owned())
+    }
+}
+
+/// Escape the characters with a meaning in rclone filter patterns.
+fn escape_glob(s: &str) -> String {
+    let mut re = String::new();
+    for c in s.chars() {
+        if "*?[]{}\\".contains(c) {
+            re.push('\\');
+        }
+        re.push(c);
+    }
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_inside_remote() {
+        assert_eq!(remote_relative("gdrive:", "gdrive:game-auto-sync.lock").as_deref(), Some("game-auto-sync.lock"));
+        assert_eq!(remote_relative("gdrive:saves/", "gdrive:saves/x/y.lock").as_deref(), Some("x/y.lock"));
+        assert_eq!(remote_relative("gdrive:saves", "gdrive:saves.lock"), None);
+        assert_eq!(remote_relative("gdrive:saves", "other:saves/x.lock"), None);
+        assert_eq!(escape_glob("a*b[1].lock"), "a\\*b\\[1\\].lock");
+    }
+
+    fn expand_test(s: &str) -> String {
+        expand(s, Path::new("/games/cfg"), "celeste")
+    }
+
+    #[test]
+    fn expand_home() {
+        let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).unwrap();
+        assert_eq!(expand_test("~"), home);
+        assert_eq!(expand_test("~/saves"), format!("{}/saves", home));
+        // Only a leading `~` is the home folder.
+        assert_eq!(expand_test("a/~/b"), "a/~/b");
+        assert_eq!(expand_test("~user/saves"), "~user/saves");
+    }
+
+    #[test]
+    fn expand_env() {
+        std::env::set_var("GAME_AUTO_SYNC_TEST_SAVES", "/data");
+        assert_eq!(expand_test("$GAME_AUTO_SYNC_TEST_SAVES/x"), "/data/x");
+        assert_eq!(expand_test("$GAME_AUTO_SYNC_TEST_SAVES.bak"), "/data.bak");
+    }
+
+    #[test]
+    fn expand_env_braces() {
+        std::env::set_var("GAME_AUTO_SYNC_TEST_NAME", "slot");
+        assert_eq!(expand_test("${GAME_AUTO_SYNC_TEST_NAME}1.sav"), "slot1.sav");
+        assert_eq!(expand_test("${GAME_AUTO_SYNC_TEST_NAME"), "${GAME_AUTO_SYNC_TEST_NAME");
+    }
+
+    #[test]
+    fn expand_placeholders() {
+        let exe_dir = crate::utils::get_exe_path_else_current().to_string_lossy().to_string();
+        assert_eq!(expand_test("{exe_dir}/rclone"), format!("{}/rclone", exe_dir));
+        assert_eq!(expand_test("{config_dir}/saves"), "/games/cfg/saves");
+        assert_eq!(expand_test("remote:{game}"), "remote:celeste");
+        assert_eq!(expand_test("{hostname}.lock"), format!("{}.lock", crate::utils::hostname()));
+    }
+
+    #[test]
+    fn expand_keeps_unknown() {
+        assert_eq!(expand_test("$GAME_AUTO_SYNC_TEST_UNSET/x"), "$GAME_AUTO_SYNC_TEST_UNSET/x");
+        assert_eq!(expand_test("${GAME_AUTO_SYNC_TEST_UNSET}"), "${GAME_AUTO_SYNC_TEST_UNSET}");
+        assert_eq!(expand_test("{unknown}/{game}"), "{unknown}/celeste");
+        assert_eq!(expand_test("cost $5 {}"), "cost $5 {}");
+    }
+
+    fn write(dir: &Path, name: &str, s: &str) -> PathBuf {
+        let p = dir.join(name);
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(&p, s).unwrap();
+        p
+    }
+
+    #[test]
+    fn include_merge_order() {
+        let dir = crate::utils::test_dir("cfg-include");
+        write(&dir, "base.yml", "rclone_flag: [-v]\nrclone_exe: base\nretention:\n  keep_last: 1\n  keep_daily: 2\n");
+        // Relative to the including file.
+        write(&dir, "sub/common.yml", "include: ../base.yml\nrclone_exe: common\nlock_path: common\n");
+        write(&dir, "over.yml", "lock_path: over\nsync_max_delete: 10\n");
+        let main = write(
+            &dir,
+            "game.yml",
+            "include: [sub/common.yml, over.yml]\nrclone_exe: game\nretention:\n  keep_last: 5\n",
+        );
+        let cfg = Config::from_file_path(&main).unwrap();
+        // The including file wins over its includes, and later includes over earlier ones.
+        assert_eq!(cfg.get_str("rclone_exe").as_deref(), Some("game"));
+        assert_eq!(cfg.get_str("lock_path").as_deref(), Some("over"));
+        assert_eq!(cfg.get_str_vec("rclone_flag"), Some(vec![String::from("-v")]));
+        assert_eq!(cfg.get("sync_max_delete").and_then(|v| v.as_i64()), Some(10));
+        // Maps are merged.
+        let r = cfg.get("retention").unwrap();
+        assert_eq!((r["keep_last"].as_i64(), r["keep_daily"].as_i64()), (Some(5), Some(2)));
+        assert!(cfg.get("include").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_expanded() {
+        let dir = crate::utils::test_dir("cfg-include-expand");
+        write(&dir, "shared/game.yml", "rclone_exe: shared\n");
+        let main = write(&dir, "game.yml", "include: \"{config_dir}/shared/{game}.yml\"\n");
+        let cfg = Config::from_file_path(&main).unwrap();
+        assert_eq!(cfg.get_str("rclone_exe").as_deref(), Some("shared"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = crate::utils::test_dir("cfg-include-cycle");
+        write(&dir, "b.yml", "include: c.yml\n");
+        write(&dir, "c.yml", "include: a.yml\n");
+        let main = write(&dir, "a.yml", "include: b.yml\n");
+        match Config::from_file_path(&main) {
+            Err(ConfigError::IncludeCycle(chain)) => {
+                let names: Vec<_> = chain.split(" -> ").map(|p| p.rsplit(['/', '\\']).next().unwrap()).collect();
+                assert_eq!(names, ["a.yml", "b.yml", "c.yml", "a.yml"]);
+            }
+            re => panic!("expected an include cycle, got {:?}", re),
+        }
+        // Including the same file twice is not a cycle.
+        write(&dir, "d.yml", "rclone_exe: d\n");
+        let main = write(&dir, "e.yml", "include: [d.yml, d.yml]\n");
+        assert!(Config::from_file_path(&main).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
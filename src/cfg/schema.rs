@@ -0,0 +1,390 @@
+use super::marked::{join, Pos, Positions};
+use yaml_rust::{yaml::Hash, Yaml};
+
+/// The expected type of a config value. `null` is always accepted and means the default.
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Bool,
+    Str,
+    /// A string or a list of strings.
+    StrVec,
+    /// A non-negative integer.
+    Int,
+    /// A non-negative integer or a list of them.
+    IntVec,
+    /// Seconds, or a string like `20m`.
+    Duration,
+    Choice(&'static [&'static str]),
+    Map(&'static [(&'static str, Kind)]),
+    /// Game profiles, each one takes the top level keys.
+    Games,
+    /// A command line, or a map with the command line and options.
+    Command(&'static [(&'static str, Kind)]),
+    /// A failure policy like `ask-with-timeout:30=continue`.
+    Policy,
+    /// A failure policy for all phases, or a map of the phases to policies.
+    Policies(&'static [&'static str]),
+}
+
+const RETENTION: &[(&str, Kind)] = &[
+    ("keep_last", Kind::Int),
+    ("keep_hourly", Kind::Int),
+    ("keep_daily", Kind::Int),
+    ("keep_weekly", Kind::Int),
+    ("keep_monthly", Kind::Int),
+];
+
+const WAIT_FOR_PROCESS: &[(&str, Kind)] = &[
+    ("name", Kind::Str),
+    ("path", Kind::Str),
+    ("cmdline", Kind::Str),
+    ("appear_timeout", Kind::Duration),
+    ("timeout", Kind::Duration),
+];
+
+const HOOK: &[(&str, Kind)] = &[
+    ("command", Kind::StrVec),
+    ("on_failure", Kind::Choice(&["abort", "continue"])),
+];
+
+const HOOK_MAP: &[(&str, Kind)] = &[
+    ("pre_restore", Kind::Command(HOOK)),
+    ("post_restore", Kind::Command(HOOK)),
+    ("pre_run", Kind::Command(HOOK)),
+    ("post_run", Kind::Command(HOOK)),
+    ("pre_backup", Kind::Command(HOOK)),
+    ("post_backup", Kind::Command(HOOK)),
+    ("pre_upload", Kind::Command(HOOK)),
+    ("post_upload", Kind::Command(HOOK)),
+    ("on_error", Kind::Command(HOOK)),
+];
+
+const RETRY: &[(&str, Kind)] = &[
+    ("attempts", Kind::Int),
+    ("delay", Kind::Duration),
+    ("max_delay", Kind::Duration),
+    ("exit_codes", Kind::IntVec),
+    ("rclone_exit_codes", Kind::IntVec),
+];
+
+pub const TOP: &[(&str, Kind)] = &[
+    ("game_exe", Kind::StrVec),
+    ("game_backuper_exe", Kind::Str),
+    ("game_backuper_cfg", Kind::Str),
+    ("backup_command", Kind::StrVec),
+    ("restore_command", Kind::StrVec),
+    ("backup_engine", Kind::Choice(&["external", "native"])),
+    ("backup_paths", Kind::StrVec),
+    ("snapshot_dir", Kind::Str),
+    ("snapshot_dedup", Kind::Bool),
+    ("snapshot_tags", Kind::StrVec),
+    ("retention", Kind::Map(RETENTION)),
+    ("pause_at_exit", Kind::Bool),
+    ("pause_on_backup_error", Kind::Bool),
+    ("rclone_exe", Kind::Str),
+    ("rclone_remote", Kind::Str),
+    ("rclone_local", Kind::Str),
+    ("rclone_flag", Kind::StrVec),
+    ("rclone_history", Kind::Bool),
+    ("rclone_history_keep", Kind::Int),
+    ("rclone_history_max_age", Kind::Duration),
+    ("conflict_check", Kind::Bool),
+    ("sync_guard", Kind::Bool),
+    ("sync_max_delete", Kind::Int),
+    ("session_lock", Kind::Bool),
+    ("lock_path", Kind::Str),
+    ("lock_expire", Kind::Duration),
+    ("continue_when_run_failed", Kind::Bool),
+    ("on_failure", Kind::Policies(crate::utils::FAILURE_PHASES)),
+    ("retry", Kind::Map(RETRY)),
+    ("state_dir", Kind::Str),
+    ("track_process_tree", Kind::Bool),
+    ("wait_for_process", Kind::Map(WAIT_FOR_PROCESS)),
+    ("autosave_interval", Kind::Duration),
+    ("autosave_upload", Kind::Bool),
+    ("watch_saves", Kind::Bool),
+    ("watch_paths", Kind::StrVec),
+    ("watch_debounce", Kind::Duration),
+    ("hooks", Kind::Map(HOOK_MAP)),
+    ("log_file", Kind::Str),
+    ("log_level", Kind::Choice(&["debug", "info", "warn", "error", "off"])),
+    ("log_max_size", Kind::Int),
+    ("log_max_files", Kind::Int),
+    ("capture_output", Kind::Bool),
+    ("capture_dir", Kind::Str),
+    ("capture_tail_lines", Kind::Int),
+    ("capture_max_files", Kind::Int),
+    ("hide_window_when_running_exe", Kind::Bool),
+    ("hook_dll", Kind::StrVec),
+    ("current_dir", Kind::Str),
+    ("include", Kind::StrVec),
+    ("extends", Kind::StrVec),
+    ("games", Kind::Games),
+];
+
+pub struct Issue {
+    pub pos: Option<Pos>,
+    pub msg: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.pos {
+            Some(p) => write!(f, "{}: {}", p, self.msg),
+            None => write!(f, "{}", self.msg),
+        }
+    }
+}
+
+fn type_name(v: &Yaml) -> &'static str {
+    match v {
+        Yaml::Real(_) => "a number",
+        Yaml::Integer(_) => "an integer",
+        Yaml::String(_) => "a string",
+        Yaml::Boolean(_) => "a boolean",
+        Yaml::Array(_) => "a list",
+        Yaml::Hash(_) => "a map",
+        Yaml::Alias(_) => "an alias",
+        Yaml::Null => "null",
+        Yaml::BadValue => "an invalid value",
+    }
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// The most similar known key, if it is close enough to be a typo.
+fn suggest(key: &str, fields: &[(&'static str, Kind)]) -> Option<&'static str> {
+    fields
+        .iter()
+        .map(|(name, _)| (levenshtein(key, name), *name))
+        .filter(|(d, name)| *d <= 2.max(name.len() / 3))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, name)| name)
+}
+
+/// Check keys and value types of a map against `fields`.
+pub fn check_map(obj: &Hash, fields: &[(&'static str, Kind)], path: &str, pos: &Positions, issues: &mut Vec<Issue>) {
+    for (k, v) in obj.iter() {
+        let key = match k.as_str() {
+            Some(k) => k,
+            None => {
+                issues.push(Issue {
+                    pos: pos.value(path),
+                    msg: format!("keys in {} should be strings", if path.is_empty() { "config" } else { path }),
+                });
+                continue;
+            }
+        };
+        let p = join(path, key);
+        match fields.iter().find(|(name, _)| *name == key) {
+            Some((_, kind)) => check_value(v, *kind, &p, pos, issues),
+            None => {
+                let msg = match suggest(key, fields) {
+                    Some(s) => format!("unknown key {}, did you mean {}?", p, join(path, s)),
+                    None => format!("unknown key {}", p),
+                };
+                issues.push(Issue {
+                    pos: pos.key(&p),
+                    msg,
+                });
+            }
+        }
+    }
+}
+
+fn check_value(v: &Yaml, kind: Kind, path: &str, pos: &Positions, issues: &mut Vec<Issue>) {
+    let expected = match (kind, v) {
+        (_, Yaml::Null) => return,
+        (Kind::Bool, Yaml::Boolean(_)) => return,
+        (Kind::Bool, _) => "a boolean".to_owned(),
+        (Kind::Str, Yaml::String(_)) => return,
+        (Kind::Str, _) => "a string".to_owned(),
+        (Kind::StrVec, Yaml::String(_)) => return,
+        (Kind::StrVec, Yaml::Array(a)) => {
+            for (i, item) in a.iter().enumerate() {
+                if !matches!(item, Yaml::String(_) | Yaml::Integer(_) | Yaml::Real(_)) {
+                    let p = join(path, &i.to_string());
+                    issues.push(Issue {
+                        pos: pos.value(&p),
+                        msg: format!("items of {} should be strings, found {}", path, type_name(item)),
+                    });
+                }
+            }
+            return;
+        }
+        (Kind::StrVec, _) => "a string or a list of strings".to_owned(),
+        (Kind::Int, Yaml::Integer(i)) if *i >= 0 => return,
+        (Kind::Int, _) => "a non-negative integer".to_owned(),
+        (Kind::IntVec, Yaml::Integer(i)) if *i >= 0 => return,
+        (Kind::IntVec, Yaml::Array(a)) => {
+            for (i, item) in a.iter().enumerate() {
+                if !matches!(item, Yaml::Integer(n) if *n >= 0) {
+                    let p = join(path, &i.to_string());
+                    issues.push(Issue {
+                        pos: pos.value(&p),
+                        msg: format!("items of {} should be non-negative integers, found {}", path, type_name(item)),
+                    });
+                }
+            }
+            return;
+        }
+        (Kind::IntVec, _) => "a non-negative integer or a list of them".to_owned(),
+        (Kind::Duration, Yaml::Integer(i)) if *i >= 0 => return,
+        (Kind::Duration, Yaml::String(s)) if crate::utils::parse_duration(s).is_some() => return,
+        (Kind::Duration, _) => "a duration like 90, 30s, 20m, 12h or 7d".to_owned(),
+        (Kind::Choice(c), Yaml::String(s)) if c.contains(&s.as_str()) => return,
+        (Kind::Choice(c), _) => format!("one of {}", c.join(", ")),
+        (Kind::Map(fields), Yaml::Hash(h)) => {
+            check_map(h, fields, path, pos, issues);
+            return;
+        }
+        (Kind::Map(_), _) => "a map".to_owned(),
+        (Kind::Games, Yaml::Hash(h)) => {
+            if path != "games" {
+                issues.push(Issue {
+                    pos: pos.key(path),
+                    msg: format!("game profiles can not be nested in {}", path),
+                });
+                return;
+            }
+            for (name, profile) in h.iter() {
+                let p = join(path, name.as_str().unwrap_or("?"));
+                match profile {
+                    Yaml::Hash(h) => {
+                        check_map(h, TOP, &p, pos, issues);
+                        for k in ["include", "extends"] {
+                            let kp = join(&p, k);
+                            if h.contains_key(&Yaml::from_str(k)) {
+                                issues.push(Issue {
+                                    pos: pos.key(&kp),
+                                    msg: format!("{} is only supported at the top level", kp),
+                                });
+                            }
+                        }
+                    }
+                    Yaml::Null => {}
+                    _ => issues.push(Issue {
+                        pos: pos.value(&p),
+                        msg: format!("{} should be a map, found {}", p, type_name(profile)),
+                    }),
+                }
+            }
+            return;
+        }
+        (Kind::Games, _) => "a map of game profiles".to_owned(),
+        (Kind::Command(_), Yaml::String(_) | Yaml::Array(_)) => return check_value(v, Kind::StrVec, path, pos, issues),
+        (Kind::Command(fields), Yaml::Hash(h)) => {
+            check_map(h, fields, path, pos, issues);
+            if !h.contains_key(&Yaml::from_str("command")) {
+                issues.push(Issue {
+                    pos: pos.value(path),
+                    msg: format!("{} need a command", path),
+                });
+            }
+            return;
+        }
+        (Kind::Command(_), _) => "a command line or a map".to_owned(),
+        (Kind::Policy, Yaml::String(s)) if crate::utils::FailurePolicy::parse(s).is_some() => return,
+        (Kind::Policy, _) => "abort, continue, ask or ask-with-timeout:<secs>=<abort|continue>".to_owned(),
+        (Kind::Policies(_), Yaml::String(_)) => return check_value(v, Kind::Policy, path, pos, issues),
+        (Kind::Policies(phases), Yaml::Hash(h)) => {
+            let fields: Vec<_> = phases.iter().map(|p| (*p, Kind::Policy)).collect();
+            check_map(h, &fields, path, pos, issues);
+            return;
+        }
+        (Kind::Policies(_), _) => "a failure policy or a map of phases to policies".to_owned(),
+    };
+    issues.push(Issue {
+        pos: pos.value(path),
+        msg: format!("{} should be {}, found {}", path, expected, type_name(v)),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use yaml_rust::YamlLoader;
+
+    fn issues(s: &str) -> Vec<String> {
+        let obj = YamlLoader::load_from_str(s).unwrap().remove(0);
+        let pos = Positions::load(s, Path::new("game.yml")).unwrap();
+        let mut issues = Vec::new();
+        check_map(obj.as_hash().unwrap(), TOP, "", &pos, &mut issues);
+        issues.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn valid() {
+        let s = "game_exe: [game.exe, -w]\nretention:\n  keep_last: 3\non_failure:\n  backup: continue\n";
+        assert!(issues(s).is_empty());
+        assert!(issues("rclone_remote: ~\nautosave_interval: 20m\n").is_empty());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(issues("foo: 1\n"), ["game.yml, line 1, column 1: unknown key foo"]);
+        assert_eq!(
+            issues("game_exe: a\nrclone_remot: x\n"),
+            ["game.yml, line 2, column 1: unknown key rclone_remot, did you mean rclone_remote?"]
+        );
+        assert_eq!(
+            issues("retention:\n  keep_lst: 1\n"),
+            ["game.yml, line 2, column 3: unknown key retention.keep_lst, did you mean retention.keep_last?"]
+        );
+        assert_eq!(
+            issues("on_failure:\n  restor: abort\n"),
+            ["game.yml, line 2, column 3: unknown key on_failure.restor, did you mean on_failure.restore?"]
+        );
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(suggest("rclone_remot", TOP), Some("rclone_remote"));
+        assert_eq!(suggest("hook", TOP), Some("hooks"));
+        assert_eq!(suggest("something_else", TOP), None);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+
+    #[test]
+    fn wrong_types() {
+        assert_eq!(
+            issues("sync_guard: yes please\n"),
+            ["game.yml, line 1, column 13: sync_guard should be a boolean, found a string"]
+        );
+        assert_eq!(
+            issues("log_max_files: -1\n"),
+            ["game.yml, line 1, column 16: log_max_files should be a non-negative integer, found an integer"]
+        );
+        assert_eq!(
+            issues("game_exe:\n  - game.exe\n  - {a: 1}\n"),
+            ["game.yml, line 3, column 5: items of game_exe should be strings, found a map"]
+        );
+        assert_eq!(
+            issues("backup_engine: copy\n"),
+            ["game.yml, line 1, column 16: backup_engine should be one of external, native, found a string"]
+        );
+        // Block maps are placed at their first key.
+        assert_eq!(
+            issues("sync_guard:\n  a: 1\n"),
+            ["game.yml, line 2, column 3: sync_guard should be a boolean, found a map"]
+        );
+        assert_eq!(
+            issues("hooks:\n  pre_run:\n    on_failure: continue\n"),
+            ["game.yml, line 3, column 5: hooks.pre_run need a command"]
+        );
+    }
+}
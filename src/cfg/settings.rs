@@ -0,0 +1,264 @@
+use super::{to_duration, BackupEngine, Config, Hook, Retry, WaitForProcess};
+use crate::log::Level;
+use crate::process::Matcher;
+use crate::snapshot::retention::Policy;
+use crate::utils::{get_exe_path_else_current, FailurePolicy};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use yaml_rust::Yaml;
+
+/// The settings of the selected game, read once from the merged config.
+#[derive(Debug, Default)]
+pub(super) struct Settings {
+    pub game_backuper_cfg: Option<String>,
+    pub game_backuper_exe: String,
+    pub backup_engine: BackupEngine,
+    pub backup_paths: Vec<String>,
+    pub snapshot_dir: PathBuf,
+    pub snapshot_dedup: bool,
+    pub snapshot_tags: Vec<String>,
+    pub retention: Option<Policy>,
+    pub game_exe: Option<Vec<String>>,
+    pub backup_command: Option<Vec<String>>,
+    pub restore_command: Option<Vec<String>>,
+    pub pause_at_exit: bool,
+    pub pause_on_backup_error: bool,
+    pub rclone_exe: String,
+    pub rclone_remote: Option<String>,
+    pub rclone_local: Option<String>,
+    pub rclone_flag: Vec<String>,
+    pub rclone_history: bool,
+    pub rclone_history_keep: Option<usize>,
+    pub rclone_history_max_age: Option<Duration>,
+    #[cfg(windows)]
+    pub hide_window_when_running_exe: bool,
+    pub conflict_check: bool,
+    pub sync_guard: bool,
+    pub sync_max_delete: usize,
+    pub session_lock: bool,
+    pub lock_path: Option<String>,
+    pub lock_expire: Duration,
+    /// `on_failure` given as one policy for all phases.
+    pub on_failure: Option<FailurePolicy>,
+    /// `on_failure` given by phase name.
+    pub on_failure_phases: HashMap<String, FailurePolicy>,
+    pub state_dir: PathBuf,
+    pub retry: Retry,
+    pub rclone_retry: Retry,
+    pub continue_when_run_failed: bool,
+    #[cfg(target_os = "linux")]
+    pub track_process_tree: bool,
+    pub wait_for_process: Option<WaitForProcess>,
+    pub autosave_interval: Option<Duration>,
+    pub watch_saves: bool,
+    pub watch_paths: Vec<String>,
+    pub watch_debounce: Duration,
+    pub autosave_upload: bool,
+    pub hooks: HashMap<String, Hook>,
+    pub log_file: PathBuf,
+    pub log_level: Option<Level>,
+    pub log_max_size: u64,
+    pub log_max_files: usize,
+    pub capture_output: bool,
+    pub capture_dir: PathBuf,
+    pub capture_tail_lines: usize,
+    pub capture_max_files: usize,
+    #[cfg(windows)]
+    pub hook_dll: Vec<String>,
+    #[cfg(windows)]
+    pub current_dir: Option<String>,
+}
+
+/// `path` relative to the directory of the executable.
+fn exe_relative(path: String) -> PathBuf {
+    let mut pb = get_exe_path_else_current();
+    pb.push(path);
+    pb
+}
+
+impl Settings {
+    pub fn load(c: &Config) -> Self {
+        let bool_or = |k: &str, default: bool| c.get_bool(k).map(|s| s.to_owned()).unwrap_or(default);
+        let count_or = |k: &str, default: usize| match c.get(k) {
+            Some(Yaml::Integer(i)) => (*i).max(0) as usize,
+            _ => default,
+        };
+        let rclone_remote = c.get_str("rclone_remote");
+        let rclone_local = c.get_str("rclone_local");
+        let backup_paths = c.get_str_vec("backup_paths").unwrap_or_default();
+        Self {
+            game_backuper_cfg: {
+                let pb = exe_relative(
+                    c.get_str("game_backuper_cfg")
+                        .unwrap_or_else(|| String::from("game_backuper.yml")),
+                );
+                pb.exists().then(|| pb.to_string_lossy().to_string())
+            },
+            game_backuper_exe: c
+                .get_str("game_backuper_exe")
+                .unwrap_or_else(|| String::from("game-backuper")),
+            backup_engine: match c.get_str("backup_engine").as_deref() {
+                Some("native") => BackupEngine::Native,
+                _ => BackupEngine::External,
+            },
+            // Defaults to `rclone_local`, so snapshots are uploaded by rclone. It is given to rclone as
+            // is, so it is not made relative to the executable here either.
+            snapshot_dir: match (c.get_str("snapshot_dir"), &rclone_local) {
+                (Some(d), _) => exe_relative(d),
+                (None, Some(l)) => PathBuf::from(l),
+                (None, None) => exe_relative(String::from("snapshots")),
+            },
+            snapshot_dedup: bool_or("snapshot_dedup", false),
+            snapshot_tags: c.get_str_vec("snapshot_tags").unwrap_or_default(),
+            retention: c.get("retention").and_then(|r| {
+                let get = |k: &str| r[k].as_i64().unwrap_or(0).max(0) as usize;
+                let policy = Policy {
+                    last: get("keep_last"),
+                    hourly: get("keep_hourly"),
+                    daily: get("keep_daily"),
+                    weekly: get("keep_weekly"),
+                    monthly: get("keep_monthly"),
+                };
+                (!policy.is_empty()).then_some(policy)
+            }),
+            game_exe: c.get_str_vec("game_exe"),
+            backup_command: c.get_str_vec("backup_command"),
+            restore_command: c.get_str_vec("restore_command"),
+            pause_at_exit: bool_or("pause_at_exit", false),
+            pause_on_backup_error: bool_or("pause_on_backup_error", false),
+            rclone_exe: c.get_str("rclone_exe").unwrap_or_else(|| String::from("rclone")),
+            rclone_flag: c.get_str_vec("rclone_flag").unwrap_or(vec!["-P".to_owned()]),
+            rclone_history: bool_or("rclone_history", false),
+            rclone_history_keep: match c.get("rclone_history_keep") {
+                Some(Yaml::Integer(i)) => Some((*i).max(0) as usize),
+                _ => None,
+            },
+            rclone_history_max_age: c.get_duration("rclone_history_max_age"),
+            #[cfg(windows)]
+            hide_window_when_running_exe: bool_or("hide_window_when_running_exe", true),
+            conflict_check: bool_or("conflict_check", true),
+            sync_guard: bool_or("sync_guard", true),
+            sync_max_delete: match c.get("sync_max_delete") {
+                Some(Yaml::Integer(i)) => (*i).clamp(0, 100) as usize,
+                _ => 50,
+            },
+            session_lock: bool_or("session_lock", true),
+            // Defaults to a `.lock` file next to `rclone_remote`.
+            lock_path: c.get_str("lock_path").or_else(|| {
+                let remote = rclone_remote.as_deref()?.trim_end_matches('/');
+                if remote.ends_with(':') {
+                    Some(format!("{}game-auto-sync.lock", remote))
+                } else {
+                    Some(format!("{}.lock", remote))
+                }
+            }),
+            lock_expire: c.get_duration("lock_expire").unwrap_or(Duration::from_secs(86400)),
+            on_failure: c.get("on_failure").and_then(|s| FailurePolicy::parse(s.as_str()?)),
+            on_failure_phases: match c.get("on_failure") {
+                Some(Yaml::Hash(h)) => h
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.as_str()?.to_owned(), FailurePolicy::parse(v.as_str()?)?)))
+                    .collect(),
+                _ => HashMap::new(),
+            },
+            state_dir: exe_relative(c.get_str("state_dir").unwrap_or_else(|| String::from("state"))),
+            retry: retry(c, false),
+            rclone_retry: retry(c, true),
+            continue_when_run_failed: bool_or("continue_when_run_failed", false),
+            #[cfg(target_os = "linux")]
+            track_process_tree: bool_or("track_process_tree", true),
+            wait_for_process: wait_for_process(c),
+            autosave_interval: c.get_duration("autosave_interval").filter(|d| !d.is_zero()),
+            watch_saves: bool_or("watch_saves", false),
+            watch_paths: c.get_str_vec("watch_paths").unwrap_or_else(|| backup_paths.clone()),
+            watch_debounce: c.get_duration("watch_debounce").unwrap_or(Duration::from_secs(10)),
+            autosave_upload: bool_or("autosave_upload", false),
+            hooks: match c.get("hooks") {
+                Some(Yaml::Hash(h)) => h
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.as_str()?.to_owned(), hook(c, v)?)))
+                    .collect(),
+                _ => HashMap::new(),
+            },
+            log_file: exe_relative(
+                c.get_str("log_file")
+                    .unwrap_or_else(|| String::from("game-auto-sync.log")),
+            ),
+            log_level: match c.get("log_level").and_then(|s| s.as_str()) {
+                Some(s) => Level::parse(s),
+                None => Some(Level::Debug),
+            },
+            log_max_size: match c.get("log_max_size") {
+                Some(Yaml::Integer(i)) => (*i).max(0) as u64,
+                _ => 1024 * 1024,
+            },
+            log_max_files: count_or("log_max_files", 5),
+            capture_output: bool_or("capture_output", false),
+            capture_dir: exe_relative(c.get_str("capture_dir").unwrap_or_else(|| String::from("output"))),
+            capture_tail_lines: count_or("capture_tail_lines", 20),
+            capture_max_files: count_or("capture_max_files", 20),
+            #[cfg(windows)]
+            hook_dll: c.get_str_vec("hook_dll").unwrap_or_default(),
+            #[cfg(windows)]
+            current_dir: c.get_str("current_dir"),
+            rclone_remote,
+            rclone_local,
+            backup_paths,
+        }
+    }
+}
+
+/// By default rclone is only retried on exit codes 2 (uncategorised error) and 5 (temporary error),
+/// the others are documented as fatal.
+fn retry(c: &Config, rclone: bool) -> Retry {
+    let r = c.get("retry").unwrap_or(&Yaml::Null);
+    let codes = |k: &str| match &r[k] {
+        Yaml::Integer(i) => Some(vec![*i as u32]),
+        Yaml::Array(a) => Some(a.iter().filter_map(|i| i.as_i64()).map(|i| i as u32).collect()),
+        _ => None,
+    };
+    Retry {
+        attempts: r["attempts"].as_i64().unwrap_or(0).max(0) as usize,
+        delay: to_duration(&r["delay"]).unwrap_or(Duration::from_secs(5)),
+        max_delay: to_duration(&r["max_delay"]).unwrap_or(Duration::from_secs(60)),
+        exit_codes: if rclone {
+            codes("rclone_exit_codes").unwrap_or(vec![2, 5])
+        } else {
+            codes("exit_codes").unwrap_or_default()
+        },
+    }
+}
+
+fn wait_for_process(c: &Config) -> Option<WaitForProcess> {
+    let w = c.get("wait_for_process")?.as_hash()?;
+    let get = |k: &str| w.get(&Yaml::from_str(k)).and_then(|v| v.as_str()).map(|s| c.expand(s));
+    let matcher = if let Some(n) = get("name") {
+        Matcher::Name(n)
+    } else if let Some(p) = get("path") {
+        Matcher::Path(p)
+    } else {
+        Matcher::Cmdline(regex::Regex::new(w[&Yaml::from_str("cmdline")].as_str()?).ok()?)
+    };
+    let duration = |k: &str| w.get(&Yaml::from_str(k)).and_then(to_duration);
+    Some(WaitForProcess {
+        matcher,
+        appear_timeout: duration("appear_timeout").unwrap_or(Duration::from_secs(60)),
+        timeout: duration("timeout").filter(|d| !d.is_zero()),
+    })
+}
+
+/// Failures abort the run unless `on_failure` is `continue`.
+fn hook(c: &Config, h: &Yaml) -> Option<Hook> {
+    match h {
+        Yaml::Hash(_) => Some(Hook {
+            command: c.to_str_vec(&h["command"])?,
+            abort_on_failure: h["on_failure"].as_str() != Some("continue"),
+        }),
+        _ => Some(Hook {
+            command: c.to_str_vec(h)?,
+            abort_on_failure: true,
+        }),
+    }
+    .filter(|h| !h.command.is_empty())
+}
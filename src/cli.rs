@@ -0,0 +1,406 @@
+use getopts::{Matches, Options, ParsingStyle};
+
+/// Subcommands and their descriptions.
+const COMMANDS: &[(&str, &str)] = &[
+    ("run", "Restore saves, run the game, then back up and upload them. The default."),
+    ("backup", "Back up and upload saves only."),
+    ("restore", "Download and restore saves only."),
+    ("list", "List local snapshots and remote saves with their size and files."),
+    ("status", "Show the state of local and remote saves."),
+    ("diff", "Compare local saves with remote saves."),
+    ("doctor", "Check the config and the tools it uses."),
+    ("prune", "Prune old snapshots. Only available when backup_engine is native."),
+    ("games", "List game profiles in the config file."),
+    ("history", "List the rclone history, or `history restore NAME` and `history prune`."),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Backup,
+    Restore,
+    List,
+    Status,
+    Diff,
+    Doctor,
+    Prune,
+    Games,
+    HistoryList,
+    HistoryRestore(String),
+    HistoryPrune,
+}
+
+impl Command {
+    /// Whether the command runs phases which can be reported and fail.
+    pub fn has_phases(&self) -> bool {
+        matches!(
+            self,
+            Self::Run | Self::Backup | Self::Restore | Self::Prune | Self::HistoryRestore(_) | Self::HistoryPrune
+        )
+    }
+}
+
+/// Parsed command line.
+pub struct Args {
+    pub command: Command,
+    pub config: Option<String>,
+    pub game: Option<String>,
+    pub dryrun: bool,
+    pub skip_restore: bool,
+    pub run_only: bool,
+    pub restore_from: Option<String>,
+    pub report: Option<String>,
+    pub json: bool,
+    pub on_failure: Vec<String>,
+}
+
+fn common_options(opts: &mut Options) {
+    opts.optflag("h", "help", "Print help message.");
+    opts.optopt("c", "config", "The location of config file.", "FILE");
+    opts.optopt("g", "game", "The game profile in config file.", "NAME");
+}
+
+fn dryrun_option(opts: &mut Options) {
+    opts.optflag("d", "dryrun", "Run without calling any process.");
+    opts.optflag("", "dry-run", "Same as --dryrun.");
+}
+
+fn phase_options(opts: &mut Options) {
+    opts.optopt("", "report", "Write a JSON report of the run to FILE.", "FILE");
+    opts.optflag("", "json", "Print a JSON report of the run at exit.");
+}
+
+fn failure_options(opts: &mut Options) {
+    opts.optmulti(
+        "",
+        "on-failure",
+        "What to do when a phase fails: abort, continue, ask or ask-with-timeout:<secs>=<abort|continue>. \
+        Prefix with a phase (restore_rclone, restore, run_exe, backup or backup_rclone) and = to set it for one phase.",
+        "[PHASE=]POLICY",
+    );
+}
+
+fn restore_from_option(opts: &mut Options) {
+    opts.optopt(
+        "",
+        "restore-from",
+        "Restore this backup instead of the latest one: a snapshot id, a history name for the saves before \
+        that upload, a time like \"2026-10-01 20:00\", or \"N sessions ago\". From the history, files created since are kept.",
+        "WHICH",
+    );
+}
+
+/// Options of `command`, or of the old interface without a command if `None`.
+fn options(command: Option<&str>) -> Options {
+    let mut opts = Options::new();
+    common_options(&mut opts);
+    match command {
+        Some("run") => {
+            dryrun_option(&mut opts);
+            opts.optflag("r", "skip-restore", "Skip restore backup.");
+            opts.optflag("R", "run-only", "Run only. Do not backup or restore.");
+            restore_from_option(&mut opts);
+            phase_options(&mut opts);
+            failure_options(&mut opts);
+        }
+        Some("backup") => {
+            dryrun_option(&mut opts);
+            phase_options(&mut opts);
+            failure_options(&mut opts);
+        }
+        Some("restore") => {
+            dryrun_option(&mut opts);
+            restore_from_option(&mut opts);
+            phase_options(&mut opts);
+            failure_options(&mut opts);
+        }
+        Some("list") => {
+            opts.optflag("", "json", "Print the list as JSON.");
+        }
+        Some("prune") => {
+            dryrun_option(&mut opts);
+            phase_options(&mut opts);
+        }
+        Some("history") => {
+            dryrun_option(&mut opts);
+            phase_options(&mut opts);
+            failure_options(&mut opts);
+        }
+        Some(_) => {}
+        None => {
+            opts.optflag("l", "list-games", "List game profiles in config file. Same as the games command.");
+            dryrun_option(&mut opts);
+            opts.optflag("r", "skip-restore", "Skip restore backup.");
+            opts.optflag("b", "backup-only", "Backup only. Same as the backup command.");
+            opts.optflag("R", "run-only", "Run only. Do not backup or restore.");
+            opts.optflag("p", "prune", "Prune old snapshots only. Same as the prune command.");
+            restore_from_option(&mut opts);
+            opts.optflag("", "history", "List the history of rclone_remote.");
+            opts.optopt(
+                "",
+                "history-restore",
+                "Restore saves as they were before the upload NAME in the history, they are uploaded at the next start. \
+                Files created since then are kept.",
+                "NAME",
+            );
+            opts.optflag("", "history-prune", "Prune the history of rclone_remote only.");
+            phase_options(&mut opts);
+            failure_options(&mut opts);
+        }
+    }
+    opts
+}
+
+fn print_usage(prog: &str, command: Option<&str>, opts: &Options) {
+    match command {
+        Some(c) => {
+            let desc = COMMANDS.iter().find(|(n, _)| *n == c).map(|(_, d)| *d).unwrap_or_default();
+            let brief = format!("Usage:\n{} {} [options]\n\n{}", prog, c, desc);
+            println!("{}", opts.usage(&brief));
+        }
+        None => {
+            let mut brief = format!("Usage:\n{} [COMMAND] [options]\n\nCommands:", prog);
+            for (n, d) in COMMANDS {
+                brief.push_str(&format!("\n    {:<10}{}", n, d));
+            }
+            brief.push_str(&format!(
+                "\n\nRun `{} COMMAND -h` for the options of a command. Without a command, these options are accepted:",
+                prog
+            ));
+            println!("{}", opts.usage(&brief));
+        }
+    }
+}
+
+fn flag(m: &Matches, name: &str) -> bool {
+    m.opt_defined(name) && m.opt_present(name)
+}
+
+fn opt_str(m: &Matches, name: &str) -> Option<String> {
+    if m.opt_defined(name) {
+        m.opt_str(name)
+    } else {
+        None
+    }
+}
+
+/// The command selected by the flags of the old interface.
+fn legacy_command(m: &Matches) -> Result<Command, String> {
+    let modes: Vec<_> = [
+        ("-l", "l"),
+        ("-b", "b"),
+        ("-R", "R"),
+        ("-p", "p"),
+        ("--history", "history"),
+        ("--history-restore", "history-restore"),
+        ("--history-prune", "history-prune"),
+    ]
+    .into_iter()
+    .filter(|(_, n)| m.opt_present(n))
+    .map(|(f, _)| f)
+    .collect();
+    if modes.len() > 1 {
+        return Err(format!("{} can not be used together.", modes.join(" and ")));
+    }
+    let mode = modes.first().copied();
+    if m.opt_present("r") && !matches!(mode, None | Some("-b")) {
+        return Err(format!("-r can not be used with {}.", mode.unwrap()));
+    }
+    if m.opt_present("restore-from") {
+        if m.opt_present("r") {
+            return Err(String::from("--restore-from can not be used with -r."));
+        }
+        if let Some(mode) = mode {
+            return Err(format!("--restore-from can not be used with {}.", mode));
+        }
+    }
+    Ok(match mode {
+        Some("-l") => Command::Games,
+        Some("-b") => Command::Backup,
+        Some("-p") => Command::Prune,
+        Some("--history") => Command::HistoryList,
+        Some("--history-restore") => Command::HistoryRestore(m.opt_str("history-restore").unwrap()),
+        Some("--history-prune") => Command::HistoryPrune,
+        _ => Command::Run,
+    })
+}
+
+/// Parse the command line. Returns `Ok(None)` if help was printed.
+pub fn parse(argv: &[String]) -> Result<Option<Args>, String> {
+    // The command is the first argument which is not an option, options may come before it.
+    let mut first = options(None);
+    first.parsing_style(ParsingStyle::StopAtFirstFree);
+    let pre = first.parse(&argv[1..]).map_err(|e| e.to_string())?;
+    let name = pre
+        .free
+        .first()
+        .map(|s| s.as_str())
+        .filter(|s| COMMANDS.iter().any(|(n, _)| n == s));
+    let mut rest = argv[1..].to_vec();
+    if name.is_some() {
+        rest.remove(rest.len() - pre.free.len());
+    }
+    let opts = options(name);
+    let m = opts.parse(&rest).map_err(|e| e.to_string())?;
+    if m.opt_present("h") {
+        print_usage(&argv[0], name, &opts);
+        return Ok(None);
+    }
+    let free: Vec<_> = m.free.iter().map(|s| s.as_str()).collect();
+    let command = match name {
+        None => {
+            if let Some(f) = free.first() {
+                return Err(format!("Unknown command: {}", f));
+            }
+            legacy_command(&m)?
+        }
+        Some("history") => match free.as_slice() {
+            [] | ["list"] => Command::HistoryList,
+            ["restore", n] => Command::HistoryRestore(n.to_string()),
+            ["prune"] => Command::HistoryPrune,
+            _ => return Err(String::from("Usage: history [list | restore NAME | prune]")),
+        },
+        Some(n) => {
+            if let Some(f) = free.first() {
+                return Err(format!("Unexpected argument for {}: {}", n, f));
+            }
+            match n {
+                "run" => Command::Run,
+                "backup" => Command::Backup,
+                "restore" => Command::Restore,
+                "list" => Command::List,
+                "status" => Command::Status,
+                "diff" => Command::Diff,
+                "doctor" => Command::Doctor,
+                "prune" => Command::Prune,
+                _ => Command::Games,
+            }
+        }
+    };
+    let skip_restore = flag(&m, "r");
+    let run_only = flag(&m, "R");
+    let restore_from = opt_str(&m, "restore-from");
+    if command == Command::Run {
+        if skip_restore && run_only {
+            return Err(String::from("-r can not be used with -R."));
+        }
+        if restore_from.is_some() && (skip_restore || run_only) {
+            return Err(format!(
+                "--restore-from can not be used with {}.",
+                if run_only { "-R" } else { "-r" }
+            ));
+        }
+    }
+    Ok(Some(Args {
+        config: opt_str(&m, "c"),
+        game: opt_str(&m, "g"),
+        dryrun: flag(&m, "d") || flag(&m, "dry-run"),
+        skip_restore,
+        run_only,
+        restore_from,
+        report: opt_str(&m, "report"),
+        json: flag(&m, "json"),
+        on_failure: if m.opt_defined("on-failure") {
+            m.opt_strs("on-failure")
+        } else {
+            Vec::new()
+        },
+        command,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Args, String> {
+        let argv: Vec<String> = std::iter::once("game-auto-sync")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect();
+        parse(&argv).map(|a| a.unwrap())
+    }
+
+    fn command(args: &[&str]) -> Command {
+        parse_args(args).unwrap().command
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse_args(args) {
+            Ok(a) => panic!("{:?} parsed as {:?}", args, a.command),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(command(&[]), Command::Run);
+        assert_eq!(command(&["backup"]), Command::Backup);
+        assert_eq!(command(&["list"]), Command::List);
+        assert_eq!(command(&["history"]), Command::HistoryList);
+        assert_eq!(
+            command(&["history", "restore", "20261001-200000"]),
+            Command::HistoryRestore(String::from("20261001-200000"))
+        );
+        assert_eq!(command(&["history", "prune"]), Command::HistoryPrune);
+        assert!(error(&["foo"]).contains("Unknown command"));
+        assert!(error(&["status", "x"]).contains("Unexpected argument"));
+        assert!(error(&["history", "restore"]).starts_with("Usage"));
+    }
+
+    #[test]
+    fn options_before_command() {
+        let a = parse_args(&["-c", "game.yml", "-g", "g1", "restore", "--restore-from", "~1", "-d"]).unwrap();
+        assert_eq!(a.command, Command::Restore);
+        assert_eq!(a.config.as_deref(), Some("game.yml"));
+        assert_eq!(a.game.as_deref(), Some("g1"));
+        assert_eq!(a.restore_from.as_deref(), Some("~1"));
+        assert!(a.dryrun);
+        assert!(parse_args(&["prune", "--dry-run"]).unwrap().dryrun);
+        assert!(parse_args(&["prune", "--dryrun"]).unwrap().dryrun);
+        assert!(parse_args(&["list", "--json"]).unwrap().json);
+    }
+
+    #[test]
+    fn options_of_other_commands() {
+        assert!(parse_args(&["backup", "-r"]).is_err());
+        assert!(parse_args(&["list", "-d"]).is_err());
+        assert!(parse_args(&["list", "--dry-run"]).is_err());
+        assert!(parse_args(&["status", "--restore-from", "~1"]).is_err());
+        assert!(parse_args(&["prune", "--on-failure", "abort"]).is_err());
+        let a = parse_args(&["history", "restore", "n", "--on-failure", "continue"]).unwrap();
+        assert_eq!(a.on_failure, ["continue"]);
+    }
+
+    #[test]
+    fn legacy_flags() {
+        assert_eq!(command(&["-b"]), Command::Backup);
+        assert_eq!(command(&["-b", "-r"]), Command::Backup);
+        assert_eq!(command(&["-p"]), Command::Prune);
+        assert_eq!(command(&["-l"]), Command::Games);
+        assert_eq!(command(&["--history-restore", "n"]), Command::HistoryRestore(String::from("n")));
+        let a = parse_args(&["-R"]).unwrap();
+        assert_eq!(a.command, Command::Run);
+        assert!(a.run_only);
+        let a = parse_args(&["-r", "--on-failure", "abort", "--on-failure", "backup=continue"]).unwrap();
+        assert!(a.skip_restore);
+        assert_eq!(a.on_failure, ["abort", "backup=continue"]);
+    }
+
+    #[test]
+    fn rejected_legacy_flags() {
+        assert_eq!(error(&["-b", "-R"]), "-b and -R can not be used together.");
+        assert_eq!(error(&["-b", "-p", "--history"]), "-b and -p and --history can not be used together.");
+        assert_eq!(error(&["-r", "-R"]), "-r can not be used with -R.");
+        assert_eq!(error(&["-r", "-p"]), "-r can not be used with -p.");
+        assert_eq!(error(&["--restore-from", "~1", "-r"]), "--restore-from can not be used with -r.");
+        assert_eq!(error(&["--restore-from", "~1", "-b"]), "--restore-from can not be used with -b.");
+    }
+
+    #[test]
+    fn rejected_run_flags() {
+        assert_eq!(error(&["run", "-r", "-R"]), "-r can not be used with -R.");
+        assert_eq!(error(&["run", "--restore-from", "~1", "-r"]), "--restore-from can not be used with -r.");
+        assert_eq!(error(&["run", "--restore-from", "~1", "-R"]), "--restore-from can not be used with -R.");
+    }
+}
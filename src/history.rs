@@ -0,0 +1,285 @@
+use crate::rclone::{self, RcloneError};
+use crate::select::Selection;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use std::time::Duration;
+
+/// Format of the folder names in the history, the time of the upload which replaced the files in them.
+const TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// The folder keeping the files replaced on `remote`, next to it.
+/// For the root of a remote like `gdrive:` it is inside, and excluded from syncs.
+pub fn base(remote: &str) -> String {
+    let remote = remote.trim_end_matches('/');
+    if remote.ends_with(':') {
+        format!("{}.game-auto-sync-history", remote)
+    } else {
+        format!("{}.history", remote)
+    }
+}
+
+/// The folder for files replaced by an upload at `time`, used as `--backup-dir` of `rclone sync`.
+pub fn dir(remote: &str, time: DateTime<Local>) -> String {
+    format!("{}/{}", base(remote), time.format(TIME_FORMAT))
+}
+
+/// Files replaced or deleted on the remote by one upload.
+#[derive(Debug)]
+pub struct Entry {
+    pub name: String,
+    pub time: DateTime<Local>,
+    pub files: usize,
+    pub size: i64,
+}
+
+/// List the history of `remote`, oldest first.
+pub fn list(exe: &str, remote: &str) -> Result<Vec<Entry>, RcloneError> {
+    let mut re: Vec<Entry> = Vec::new();
+    for i in rclone::lsjson(exe, &base(remote), false, &[])? {
+        let (name, _) = match i.path.split_once('/') {
+            Some(s) => s,
+            None => continue,
+        };
+        if let Some(e) = re.iter_mut().find(|e| e.name == name) {
+            e.files += 1;
+            e.size += i.size;
+            continue;
+        }
+        let time = match NaiveDateTime::parse_from_str(name, TIME_FORMAT)
+            .ok()
+            .and_then(|t| Local.from_local_datetime(&t).earliest())
+        {
+            Some(t) => t,
+            None => continue,
+        };
+        re.push(Entry {
+            name: name.to_owned(),
+            time,
+            files: 1,
+            size: i.size,
+        });
+    }
+    re.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(re)
+}
+
+/// Commands to download `remote` into `local` and undo the uploads of `entries` there, so the saves
+/// are as they were before the first of them. Only replaced and deleted files are in the history,
+/// files first created by these uploads are kept.
+pub fn restore_cmls(
+    exe: &str,
+    remote: &str,
+    local: &str,
+    entries: &[Entry],
+    exclude: &[String],
+    flags: &[String],
+) -> Vec<Vec<String>> {
+    let mut sync = vec![exe.to_owned(), String::from("sync"), remote.to_owned(), local.to_owned()];
+    sync.extend_from_slice(exclude);
+    let mut cmls = vec![sync];
+    // Newer folders first, so the version replaced by the oldest upload wins.
+    for e in entries.iter().rev() {
+        cmls.push(vec![exe.to_owned(), String::from("copy"), dir(remote, e.time), local.to_owned()]);
+    }
+    for c in cmls.iter_mut() {
+        c.extend_from_slice(flags);
+    }
+    cmls
+}
+
+/// Select the entries to remove, keeping the newest `keep` ones and those not older than `max_age`.
+/// An entry is kept if any of the set rules keeps it. `entries` must be sorted oldest first.
+pub fn select_prune(entries: &[Entry], keep: Option<usize>, max_age: Option<Duration>) -> Vec<&Entry> {
+    if keep.is_none() && max_age.is_none() {
+        return Vec::new();
+    }
+    let now = Local::now();
+    let len = entries.len();
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(i, e)| {
+            let by_count = keep.is_some_and(|k| len - i <= k);
+            let by_age = max_age.is_some_and(|a| (now - e.time).to_std().map(|d| d <= a).unwrap_or(true));
+            !by_count && !by_age
+        })
+        .map(|(_, e)| e)
+        .collect()
+}
+
+/// The first entry to undo to get the saves selected by `selection`, so all entries from it on are undone.
+/// Returns `entries.len()` if the current remote saves are selected and `None` if nothing matches.
+pub fn start(entries: &[Entry], selection: &Selection) -> Option<usize> {
+    match selection {
+        Selection::Latest => Some(entries.len()),
+        // The saves before the upload `s`, same as `--history-restore`.
+        Selection::Id(s) => entries.iter().position(|e| &e.name == s),
+        Selection::AsOf(t) => Some(entries.iter().position(|e| e.time > *t).unwrap_or(entries.len())),
+        Selection::Ago(n) => entries.len().checked_sub(*n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(ages: &[u64]) -> Vec<Entry> {
+        // Oldest first, ages in hours.
+        ages.iter()
+            .map(|h| {
+                let time = Local::now() - chrono::Duration::hours(*h as i64);
+                Entry {
+                    name: time.format(TIME_FORMAT).to_string(),
+                    time,
+                    files: 1,
+                    size: 1,
+                }
+            })
+            .collect()
+    }
+
+    fn names(entries: &[&Entry]) -> Vec<String> {
+        entries.iter().map(|e| e.name.clone()).collect()
+    }
+
+    #[test]
+    fn base_next_to_remote() {
+        assert_eq!(base("gdrive:saves/game"), "gdrive:saves/game.history");
+        assert_eq!(base("gdrive:saves/game/"), "gdrive:saves/game.history");
+        assert_eq!(base("gdrive:"), "gdrive:.game-auto-sync-history");
+        assert_eq!(base("gdrive:/"), "gdrive:.game-auto-sync-history");
+    }
+
+    #[test]
+    fn dir_by_time() {
+        let t = Local.with_ymd_and_hms(2026, 10, 1, 20, 5, 9).unwrap();
+        assert_eq!(dir("remote:game", t), "remote:game.history/20261001-200509");
+    }
+
+    #[test]
+    fn prune_without_rules() {
+        let e = entries(&[100, 50, 1]);
+        assert!(select_prune(&e, None, None).is_empty());
+    }
+
+    #[test]
+    fn prune_by_count() {
+        let e = entries(&[100, 50, 1]);
+        assert_eq!(names(&select_prune(&e, Some(2), None)), [e[0].name.clone()]);
+        assert!(select_prune(&e, Some(3), None).is_empty());
+        assert_eq!(select_prune(&e, Some(0), None).len(), 3);
+    }
+
+    #[test]
+    fn prune_by_age() {
+        let e = entries(&[100, 50, 1]);
+        let day = Duration::from_secs(86400);
+        assert_eq!(names(&select_prune(&e, None, Some(day))), [e[0].name.clone(), e[1].name.clone()]);
+    }
+
+    #[test]
+    fn prune_keeps_by_any_rule() {
+        let e = entries(&[100, 50, 30, 1]);
+        let day = Duration::from_secs(86400);
+        // keep 2 keeps the last two, the age keeps the newest one only.
+        assert_eq!(names(&select_prune(&e, Some(2), Some(day))), [e[0].name.clone(), e[1].name.clone()]);
+        // The age keeps more than the count.
+        let week = Duration::from_secs(7 * 86400);
+        assert!(select_prune(&e, Some(1), Some(week)).is_empty());
+    }
+
+    /// Uploads at 10:00, 20:00 and 08:00 the next day, oldest first.
+    fn uploads() -> Vec<Entry> {
+        [(1, 10), (1, 20), (2, 8)]
+            .iter()
+            .map(|(d, h)| {
+                let time = Local.with_ymd_and_hms(2026, 10, *d, *h, 0, 0).unwrap();
+                Entry {
+                    name: time.format(TIME_FORMAT).to_string(),
+                    time,
+                    files: 1,
+                    size: 1,
+                }
+            })
+            .collect()
+    }
+
+    fn cmls(entries: &[Entry]) -> Vec<Vec<String>> {
+        let strs = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        restore_cmls("rclone", "remote:game", "saves", entries, &strs(&["--exclude", "/game.lock"]), &strs(&["-P"]))
+    }
+
+    #[test]
+    fn restore_commands() {
+        let e = uploads();
+        assert_eq!(
+            cmls(&e[1..]),
+            [
+                ["rclone", "sync", "remote:game", "saves", "--exclude", "/game.lock", "-P"].as_slice(),
+                &["rclone", "copy", "remote:game.history/20261002-080000", "saves", "-P"],
+                &["rclone", "copy", "remote:game.history/20261001-200000", "saves", "-P"],
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_commands_keep_new_files() {
+        // Only the first command may delete, it makes the saves the same as the remote. Files first
+        // uploaded after the selected point are in no history folder, so nothing removes them.
+        for c in cmls(&uploads()).iter().skip(1) {
+            assert_eq!(c[1], "copy");
+        }
+        assert_eq!(cmls(&[]).len(), 1);
+    }
+
+    fn start_of(sel: &str) -> Option<usize> {
+        start(&uploads(), &Selection::parse(sel))
+    }
+
+    #[test]
+    fn latest_undoes_nothing() {
+        assert_eq!(start_of("latest"), Some(3));
+        assert_eq!(start_of("0 sessions ago"), Some(3));
+        assert_eq!(start(&[], &Selection::Latest), Some(0));
+    }
+
+    #[test]
+    fn by_name() {
+        assert_eq!(start_of("20261001-200000"), Some(1));
+        assert_eq!(start_of("20261001-200001"), None);
+    }
+
+    #[test]
+    fn sessions_ago() {
+        assert_eq!(start_of("1 session ago"), Some(2));
+        assert_eq!(start_of("~3"), Some(0));
+        assert_eq!(start_of("4 sessions ago"), None);
+    }
+
+    #[test]
+    fn selection_commands() {
+        let e = uploads();
+        let copies = |sel: &str| {
+            let i = start_of(sel).unwrap();
+            cmls(&e[i..]).iter().skip(1).map(|c| c[2].clone()).collect::<Vec<_>>()
+        };
+        // The name selects the saves before that upload, so its own folder is copied last.
+        assert_eq!(
+            copies("20261001-200000"),
+            ["remote:game.history/20261002-080000", "remote:game.history/20261001-200000"]
+        );
+        assert_eq!(copies("1 session ago"), ["remote:game.history/20261002-080000"]);
+        assert_eq!(copies("2026-10-01 12:00"), copies("20261001-200000"));
+        assert!(copies("latest").is_empty());
+    }
+
+    #[test]
+    fn as_of() {
+        // An upload exactly at the time is kept, the ones after it are undone.
+        assert_eq!(start_of("2026-10-01 20:00"), Some(2));
+        assert_eq!(start_of("2026-10-01 19:59"), Some(1));
+        assert_eq!(start_of("2026-10-01"), Some(2));
+        assert_eq!(start_of("2026-10-02 09:00"), Some(3));
+        // Before the first upload, all of them are undone.
+        assert_eq!(start_of("2026-09-30"), Some(0));
+    }
+}
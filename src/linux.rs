@@ -0,0 +1,202 @@
+use crate::capture::Capture;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, Stdio};
+use subprocess::ExitStatus;
+
+/// Send all output to stdout of this process and the commands it starts to stderr,
+/// and return the original stdout.
+pub fn take_stdout() -> io::Result<File> {
+    io::stdout().flush()?;
+    let fd = unsafe { libc::fcntl(libc::STDOUT_FILENO, libc::F_DUPFD_CLOEXEC, 3) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let out = unsafe { File::from_raw_fd(fd) };
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(out)
+}
+
+fn set_subreaper(enable: bool) -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, enable as libc::c_ulong, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The terminal on stdin, if this process is in its foreground process group.
+fn foreground_tty() -> Option<libc::c_int> {
+    let fd = libc::STDIN_FILENO;
+    let foreground = unsafe { libc::isatty(fd) == 1 && libc::tcgetpgrp(fd) == libc::getpgrp() };
+    foreground.then_some(fd)
+}
+
+/// Make `pgid` the foreground process group of the terminal `fd`.
+/// SIGTTOU is ignored meanwhile, since the caller may be in a background group.
+/// Only async-signal-safe calls are made, so this can run in the child before exec.
+fn set_foreground(fd: libc::c_int, pgid: libc::pid_t) -> io::Result<()> {
+    unsafe {
+        let old = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        let re = libc::tcsetpgrp(fd, pgid);
+        libc::signal(libc::SIGTTOU, old);
+        if re != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The game in the foreground was stopped, e.g. by Ctrl+Z. Stop this process too, like a shell would,
+/// and continue the game in the foreground when this process is continued.
+fn suspend(pgid: libc::pid_t, tty: libc::c_int) {
+    let _ = set_foreground(tty, unsafe { libc::getpgrp() });
+    unsafe {
+        libc::raise(libc::SIGTSTP);
+    }
+    let _ = set_foreground(tty, pgid);
+    unsafe {
+        libc::kill(-pgid, libc::SIGCONT);
+    }
+}
+
+/// Wait for `pid`, or any process in a group if `pid` is negative.
+/// Returns `None` if there is nothing left to wait for.
+fn wait(pid: libc::pid_t, options: libc::c_int) -> io::Result<Option<(libc::pid_t, i32)>> {
+    loop {
+        let mut status = 0;
+        let re = unsafe { libc::waitpid(pid, &mut status, options) };
+        if re != -1 {
+            return Ok(Some((re, status)));
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::ECHILD) => return Ok(None),
+            _ => return Err(e),
+        }
+    }
+}
+
+/// Children of this process which left our process group.
+/// Processes we start ourselves stay in our group, so these can only come from the game.
+fn escaped_children() -> Vec<libc::pid_t> {
+    let me = std::process::id() as libc::pid_t;
+    let group = unsafe { libc::getpgrp() };
+    let mut re = Vec::new();
+    let dir = match fs::read_dir("/proc") {
+        Ok(d) => d,
+        Err(_) => return re,
+    };
+    for e in dir.flatten() {
+        let stat = match fs::read_to_string(e.path().join("stat")) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        // pid (comm) state ppid pgrp ...; comm may contain spaces.
+        let fields: Vec<_> = match stat.rfind(')') {
+            Some(i) => stat[i + 1..].split_whitespace().collect(),
+            None => continue,
+        };
+        let ppid = fields.get(1).and_then(|s| s.parse::<libc::pid_t>().ok());
+        let pgrp = fields.get(2).and_then(|s| s.parse::<libc::pid_t>().ok());
+        if ppid == Some(me) && pgrp != Some(group) {
+            if let Some(pid) = e.file_name().to_str().and_then(|s| s.parse().ok()) {
+                re.push(pid);
+            }
+        }
+    }
+    re
+}
+
+/// Wait for the process group `pgid` and processes which left it, returns the status of its leader.
+/// `tty` is the terminal the group is in the foreground of.
+fn wait_tree(pgid: libc::pid_t, tty: Option<libc::c_int>) -> io::Result<i32> {
+    let mut status = 0;
+    let options = if tty.is_some() { libc::WUNTRACED } else { 0 };
+    while let Some((pid, s)) = wait(-pgid, options)? {
+        if libc::WIFSTOPPED(s) {
+            if let Some(fd) = tty {
+                suspend(pgid, fd);
+            }
+            continue;
+        }
+        if pid == pgid {
+            status = s;
+        }
+    }
+    // Processes which moved to another group, e.g. daemonized by a launcher.
+    loop {
+        let escaped = escaped_children();
+        if escaped.is_empty() {
+            return Ok(status);
+        }
+        for pid in escaped {
+            wait(pid, 0)?;
+        }
+    }
+}
+
+/// Run `argv` and wait until it and every process it started have exited,
+/// like the job object used on Windows.
+///
+/// This process becomes a child subreaper, so orphaned descendants are re-parented to it,
+/// and the game is started in its own process group, so they can be told apart from
+/// processes started by this program. The group is made the foreground group of the terminal
+/// while it runs, so the game can read from it and gets Ctrl+C instead of this program.
+///
+/// The output is copied to `capture` if given.
+pub fn call<S: AsRef<OsStr>>(argv: &[S], capture: Option<&Capture>) -> io::Result<ExitStatus> {
+    set_subreaper(true)?;
+    let tty = foreground_tty();
+    let mut cmd = Command::new(argv[0].as_ref());
+    cmd.args(&argv[1..]).process_group(0);
+    if let Some(fd) = tty {
+        // Also done by the parent below, whichever runs first, the game starts in the foreground.
+        unsafe {
+            cmd.pre_exec(move || {
+                let _ = set_foreground(fd, libc::getpid());
+                Ok(())
+            });
+        }
+    }
+    if capture.is_some() {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let child = cmd.spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => {
+            let _ = set_subreaper(false);
+            return Err(e);
+        }
+    };
+    let mut tee = Vec::new();
+    if let Some(c) = capture {
+        tee.extend(child.stdout.take().map(|o| c.tee(o, false)));
+        tee.extend(child.stderr.take().map(|e| c.tee(e, true)));
+    }
+    let pgid = child.id() as libc::pid_t;
+    if let Some(fd) = tty {
+        let _ = set_foreground(fd, pgid);
+    }
+    let re = wait_tree(pgid, tty);
+    if let Some(fd) = tty {
+        let _ = set_foreground(fd, unsafe { libc::getpgrp() });
+    }
+    let _ = set_subreaper(false);
+    for t in tee {
+        let _ = t.join();
+    }
+    let status = re?;
+    let status = std::process::ExitStatus::from_raw(status);
+    Ok(match (status.code(), status.signal()) {
+        (Some(c), _) => ExitStatus::Exited(c as u32),
+        (None, Some(s)) => ExitStatus::Signaled(s as u8),
+        _ => ExitStatus::Undetermined,
+    })
+}
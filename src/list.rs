@@ -0,0 +1,46 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+/// A backup shown by the `list` command.
+#[derive(Serialize)]
+pub struct Backup {
+    /// `local` for native snapshots, `remote` for the saves on `rclone_remote`,
+    /// or `history` for files replaced on the remote by an upload.
+    pub location: &'static str,
+    pub id: String,
+    /// For the remote, the time of its newest file; for history, the time of the upload.
+    pub time: Option<DateTime<Local>>,
+    pub size: u64,
+    pub files: usize,
+    /// The machine which made the backup, if known.
+    pub host: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Print `backups` as a table.
+pub fn print_table(backups: &[Backup]) {
+    if backups.is_empty() {
+        println!("No backups found.");
+        return;
+    }
+    let id_width = backups.iter().map(|b| b.id.len()).max().unwrap_or(0).max(2);
+    println!(
+        "{:<8}  {:<id_width$}  {:<19}  {:>10}  {:>6}  {:<16}  TAGS",
+        "LOCATION", "ID", "TIME", "SIZE", "FILES", "HOST"
+    );
+    for b in backups {
+        let line = format!(
+            "{:<8}  {:<id_width$}  {:<19}  {:>10}  {:>6}  {:<16}  {}",
+            b.location,
+            b.id,
+            b.time
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| String::from("-")),
+            crate::utils::format_size(b.size as i64),
+            b.files,
+            b.host.as_deref().unwrap_or("-"),
+            b.tags.join(",")
+        );
+        println!("{}", line.trim_end());
+    }
+}
@@ -0,0 +1,53 @@
+use chrono::{DateTime, Local};
+use std::time::Duration;
+use yaml_rust::{yaml::Hash, Yaml, YamlEmitter, YamlLoader};
+
+/// Written to the remote while a machine is playing.
+#[derive(Debug)]
+pub struct SessionLock {
+    pub host: String,
+    pub pid: u32,
+    pub time: DateTime<Local>,
+}
+
+impl SessionLock {
+    pub fn new() -> Self {
+        Self {
+            host: crate::utils::hostname(),
+            pid: std::process::id(),
+            time: Local::now(),
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let doc = YamlLoader::load_from_str(s).ok()?;
+        let doc = doc.first()?;
+        Some(Self {
+            host: doc["host"].as_str()?.to_owned(),
+            pid: doc["pid"].as_i64()? as u32,
+            time: DateTime::parse_from_rfc3339(doc["time"].as_str()?)
+                .ok()?
+                .with_timezone(&Local),
+        })
+    }
+
+    pub fn dump(&self) -> String {
+        let mut obj = Hash::new();
+        obj.insert(Yaml::from_str("host"), Yaml::String(self.host.clone()));
+        obj.insert(Yaml::from_str("pid"), Yaml::Integer(self.pid as i64));
+        obj.insert(Yaml::from_str("time"), Yaml::String(self.time.to_rfc3339()));
+        let mut s = String::new();
+        YamlEmitter::new(&mut s).dump(&Yaml::Hash(obj)).unwrap();
+        s.push('\n');
+        s
+    }
+
+    pub fn is_expired(&self, expire: Duration) -> bool {
+        (Local::now() - self.time).to_std().is_ok_and(|d| d > expire)
+    }
+
+    /// Whether the lock was left by this machine.
+    pub fn is_own(&self) -> bool {
+        self.host == crate::utils::hostname()
+    }
+}
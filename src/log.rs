@@ -0,0 +1,164 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "debug" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+        }
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    level: Level,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl LogFile {
+    /// Move `log` to `log.1`, `log.1` to `log.2` and so on, keeping `max_files` old files.
+    fn rotate(path: &Path, max_files: usize) {
+        let old = |i: usize| {
+            let mut s = path.as_os_str().to_owned();
+            s.push(format!(".{}", i));
+            PathBuf::from(s)
+        };
+        let _ = fs::remove_file(old(max_files));
+        for i in (1..max_files).rev() {
+            let _ = fs::rename(old(i), old(i + 1));
+        }
+        if max_files == 0 {
+            let _ = fs::remove_file(path);
+        } else {
+            let _ = fs::rename(path, old(1));
+        }
+    }
+
+    fn open(path: &Path, max_size: u64, max_files: usize) -> std::io::Result<(File, u64)> {
+        if let Some(p) = path.parent() {
+            fs::create_dir_all(p)?;
+        }
+        if fs::metadata(path).is_ok_and(|m| max_size > 0 && m.len() >= max_size) {
+            Self::rotate(path, max_files);
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.max_size > 0 && self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            Self::rotate(&self.path, self.max_files);
+            match OpenOptions::new().create(true).append(true).open(&self.path) {
+                Ok(file) => {
+                    self.file = file;
+                    self.size = 0;
+                }
+                Err(e) => println!("Failed to rotate log file {}: {}.", self.path.display(), e),
+            }
+        }
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+}
+
+struct State {
+    file: Option<LogFile>,
+    phase: &'static str,
+}
+
+static STATE: Mutex<State> = Mutex::new(State { file: None, phase: "main" });
+
+/// Start writing messages of `level` or above to `path`.
+///
+/// The file is rotated when it would grow over `max_size` bytes, 0 means no limit.
+/// `max_files` rotated files are kept besides the current one.
+pub fn init(path: &Path, level: Level, max_size: u64, max_files: usize) -> std::io::Result<()> {
+    let (file, size) = LogFile::open(path, max_size, max_files)?;
+    STATE.lock().unwrap().file = Some(LogFile {
+        path: path.to_owned(),
+        file,
+        size,
+        level,
+        max_size,
+        max_files,
+    });
+    Ok(())
+}
+
+/// Set the phase written with later messages.
+pub fn set_phase(phase: &'static str) {
+    STATE.lock().unwrap().phase = phase;
+}
+
+/// The phase set by [set_phase].
+pub fn phase() -> &'static str {
+    STATE.lock().unwrap().phase
+}
+
+/// Write `msg` to the log file. Messages above debug level are printed as well.
+pub fn log(level: Level, msg: &str) {
+    if level >= Level::Info {
+        println!("{}", msg);
+    }
+    let mut state = STATE.lock().unwrap();
+    let phase = state.phase;
+    if let Some(f) = state.file.as_mut() {
+        if level >= f.level {
+            let line = format!(
+                "{} {:<5} [{}] {}\n",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                level.name(),
+                phase,
+                msg
+            );
+            f.write(&line);
+        }
+    }
+}
+
+/// Only written to the log file.
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Debug, &format!($($arg)*)) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Info, &format!($($arg)*)) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Warn, &format!($($arg)*)) };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Error, &format!($($arg)*)) };
+}
+
+pub(crate) use {debug, error, info, warning};
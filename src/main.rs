@@ -1,20 +1,33 @@
+mod capture;
 mod cfg;
+mod cli;
+mod history;
+mod list;
+#[cfg(target_os = "linux")]
+mod linux;
+mod lock;
+mod log;
+mod pending;
+mod process;
+mod rclone;
+mod report;
+mod select;
+mod snapshot;
 mod utils;
+mod watch;
 #[cfg(windows)]
 mod windows;
 
-use getopts::Options;
+use cli::Command;
+use log::{debug, error, info, warning};
+use std::collections::HashMap;
+use std::io::Write;
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
 use subprocess::ExitStatus;
-
-pub fn print_usage(prog: &str, opts: &Options) {
-    let brief = format!(
-        "{}
-{} [options]",
-        "Usage:", prog,
-    );
-    println!("{}", opts.usage(brief.as_str()));
-}
+use utils::FailurePolicy;
 
 #[derive(Debug, derive_more::Display, derive_more::From)]
 enum Error {
@@ -22,136 +35,662 @@ enum Error {
     Popen(subprocess::PopenError),
     #[cfg(windows)]
     Popen(windows::PopenError),
+    Snapshot(snapshot::SnapshotError),
+    Rclone(rclone::RcloneError),
+    IO(std::io::Error),
     Exited,
+    /// A phase failed, with the last lines of output of its commands.
+    #[display(fmt = "{}\nLast output of {}:\n  {}", _1, _0, "_2.join(\"\\n  \")")]
+    #[from(ignore)]
+    Output(&'static str, Box<Error>, Vec<String>),
+}
+
+/// Where the backup selected by `--restore-from` is looked up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RestoreFrom {
+    /// The native snapshot store.
+    Native,
+    /// `restore_command` receives it in `{restore_from}`.
+    Command,
+    /// The rclone history of the remote.
+    History,
+}
+
+/// The outcome of uploading saves a previous session failed to upload.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pending {
+    /// Nothing was pending, or it is uploaded now.
+    Uploaded,
+    /// Still not uploaded, so the remote saves are older than the local ones.
+    Failed,
+    /// Dropped for the remote saves, which need no other conflict check before restoring them.
+    Dropped,
 }
 
 struct Main {
     _cfg: cfg::Config,
     _dryrun: bool,
     _rclone_enable: bool,
+    /// Set when the user chooses to play offline, disables rclone for this session.
+    _offline: AtomicBool,
+    /// Whether this session holds the remote lock.
+    _locked: AtomicBool,
     _skip_restore: bool,
     _backup_only: bool,
     _run_only: bool,
+    _restore_only: bool,
+    /// Failure policies of phases from the command line.
+    _on_failure: HashMap<String, FailurePolicy>,
+    /// The failure policy of all phases from the command line.
+    _on_failure_all: Option<FailurePolicy>,
+    /// The backup to restore, see [select::Selection::parse].
+    _restore_from: Option<String>,
 }
 
 impl Main {
-    fn new(cfg: cfg::Config, dryrun: bool, skip_restore: bool, backup_only: bool, run_only: bool) -> Self {
+    fn new(
+        cfg: cfg::Config,
+        dryrun: bool,
+        skip_restore: bool,
+        backup_only: bool,
+        run_only: bool,
+        restore_only: bool,
+    ) -> Self {
         Self {
             _rclone_enable: cfg.rclone_remote().is_some() && cfg.rclone_local().is_some(),
+            _offline: AtomicBool::new(false),
+            _locked: AtomicBool::new(false),
             _cfg: cfg,
             _dryrun: dryrun,
             _skip_restore: skip_restore,
             _backup_only: backup_only,
             _run_only: run_only,
+            _restore_only: restore_only,
+            _on_failure: HashMap::new(),
+            _on_failure_all: None,
+            _restore_from: None,
+        }
+    }
+
+    /// Where `--restore-from` applies: the native store, then a restore command using `{restore_from}`,
+    /// then the rclone history. `None` if none of them is available.
+    fn restore_from_target(&self) -> Option<RestoreFrom> {
+        if self._cfg.backup_engine() == cfg::BackupEngine::Native {
+            Some(RestoreFrom::Native)
+        } else if self
+            ._cfg
+            .restore_command()
+            .is_some_and(|c| c.iter().any(|a| a.contains("{restore_from}")))
+        {
+            Some(RestoreFrom::Command)
+        } else if self._rclone_enable && self._cfg.rclone_history() {
+            Some(RestoreFrom::History)
+        } else {
+            None
+        }
+    }
+
+    /// The `--restore-from` selection if it applies to `target`.
+    fn selection(&self, target: RestoreFrom) -> Option<select::Selection> {
+        if self.restore_from_target() != Some(target) {
+            return None;
+        }
+        self._restore_from.as_deref().map(select::Selection::parse)
+    }
+
+    fn rclone_enabled(&self) -> bool {
+        self._rclone_enable && !self._offline.load(Ordering::SeqCst)
+    }
+
+    /// Write the session lock to the remote, asking the user if another machine holds it.
+    fn acquire_lock(&self) -> Result<(), Error> {
+        let exe = self._cfg.rclone_exe();
+        let path = self._cfg.lock_path().unwrap();
+        if self._dryrun {
+            info!("Session lock: {}", path);
+            return Ok(());
+        }
+        match rclone::cat(&exe, &path) {
+            Ok(Some(s)) => match lock::SessionLock::parse(&s) {
+                Some(l) if l.is_own() => {
+                    info!("Found a lock left by a previous session on this machine.");
+                }
+                Some(l) if l.is_expired(self._cfg.lock_expire()) => {
+                    warning!(
+                        "Found a stale lock held by {} since {}, taking it over.",
+                        l.host,
+                        l.time.format("%Y-%m-%d %H:%M:%S")
+                    );
+                }
+                Some(l) => {
+                    warning!(
+                        "The save is locked by {} (PID {}) since {}.",
+                        l.host,
+                        l.pid,
+                        l.time.format("%Y-%m-%d %H:%M:%S")
+                    );
+                    let choices = [
+                        ("a", "Abort."),
+                        ("s", "Steal the lock."),
+                        ("o", "Play offline. Do not sync with remote in this session."),
+                    ];
+                    match self.choose("restore_rclone", &choices, 2)? {
+                        0 => return Err(Error::Exited),
+                        1 => {}
+                        _ => {
+                            self._offline.store(true, Ordering::SeqCst);
+                            return Ok(());
+                        }
+                    }
+                }
+                None => warning!("Invalid session lock found in {}, overwriting it.", path),
+            },
+            Ok(None) => {}
+            Err(e) => {
+                warning!("Failed to read session lock: {}.", e);
+                if !self.should_continue("restore_rclone", FailurePolicy::Ask) {
+                    return Err(Error::Exited);
+                }
+                return Ok(());
+            }
+        }
+        match rclone::rcat(&exe, &path, &lock::SessionLock::new().dump()) {
+            Ok(_) => self._locked.store(true, Ordering::SeqCst),
+            Err(e) => {
+                warning!("Failed to write session lock: {}.", e);
+                if !self.should_continue("restore_rclone", FailurePolicy::Ask) {
+                    return Err(Error::Exited);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn release_lock(&self) {
+        if !self._locked.load(Ordering::SeqCst) {
+            return;
         }
+        let path = self._cfg.lock_path().unwrap();
+        match rclone::deletefile(&self._cfg.rclone_exe(), &path) {
+            Ok(_) => self._locked.store(false, Ordering::SeqCst),
+            Err(e) => warning!("Failed to remove session lock {}: {}.", path, e),
+        }
+    }
+
+    fn default_backup_cml(&self) -> Vec<String> {
+        let mut def = vec![self._cfg.game_backuper_exe()];
+        if let Some(cfg_path) = self._cfg.game_backuper_cfg() {
+            def.push(String::from("-c"));
+            def.push(cfg_path);
+        }
+        def.push(String::from("backup"));
+        def
+    }
+
+    fn default_restore_cml(&self) -> Vec<String> {
+        let mut def = vec![self._cfg.game_backuper_exe()];
+        if let Some(cfg_path) = self._cfg.game_backuper_cfg() {
+            def.push(String::from("-c"));
+            def.push(cfg_path);
+        }
+        def.push(String::from("restore"));
+        def
     }
 
     fn backup(&self) -> Result<(), Error> {
+        if self._cfg.backup_engine() == cfg::BackupEngine::Native {
+            return self.backup_native();
+        }
         let cml = match self._cfg.backup_command() {
             Some(cml) => cml,
-            None => {
-                let mut def = vec![self._cfg.game_backuper_exe()];
-                if let Some(cfg_path) = self._cfg.game_backuper_cfg() {
-                    def.push(String::from("-c"));
-                    def.push(cfg_path);
-                }
-                def.push(String::from("backup"));
-                def
-            }
+            None => self.default_backup_cml(),
         };
+        report::command(&cml);
         if self._dryrun {
-            println!("Backup command line: {:?}", cml);
+            info!("Backup command line: {:?}", cml);
             Ok(())
         } else {
-            let e = Self::call(cml)?;
+            let e = self.call_retry(cml, false)?;
+            report::status(&e);
             let ok = match &e {
                 ExitStatus::Exited(c) => *c == 0,
                 _ => false,
             };
             if !ok {
-                println!("Backup failed: {:?}.", e);
-                if self._rclone_enable {
-                    if !utils::ask_continue() {
-                        return Err(Error::Exited);
-                    }
-                    return Ok(());
-                }
-                return Err(Error::Exited);
+                warning!("Backup failed: {:?}.", e);
+                return self.backup_failed();
             }
             Ok(())
         }
     }
 
-    fn backup_rclone(&self) -> Result<(), Error> {
+    fn backup_failed(&self) -> Result<(), Error> {
+        // Without rclone there is nothing left to do after the backup.
+        let default = if self.rclone_enabled() {
+            FailurePolicy::Ask
+        } else {
+            FailurePolicy::Abort
+        };
+        if self.should_continue("backup", default) {
+            return Ok(());
+        }
+        Err(Error::Exited)
+    }
+
+    /// Whether to continue after `phase` failed, by the policy from the command line or config,
+    /// or `default`.
+    fn should_continue(&self, phase: &str, default: FailurePolicy) -> bool {
+        self.policy(phase, default).should_continue()
+    }
+
+    /// Pick one of `choices` in `phase` by its failure policy: `safe` if it continues, or ask the user.
+    /// Returns an error if it aborts.
+    fn choose(&self, phase: &str, choices: &[(&str, &str)], safe: usize) -> Result<usize, Error> {
+        self.policy(phase, FailurePolicy::Ask)
+            .choose(choices, safe)
+            .ok_or(Error::Exited)
+    }
+
+    /// The failure policy of `phase` from the command line or config, or `default`.
+    fn policy(&self, phase: &str, default: FailurePolicy) -> FailurePolicy {
+        let policy = self
+            ._on_failure
+            .get(phase)
+            .copied()
+            .or(self._on_failure_all)
+            .or_else(|| self._cfg.on_failure(phase))
+            .unwrap_or(default);
+        debug!("Failure policy of {}: {:?}", phase, policy);
+        policy
+    }
+
+    fn backup_native(&self) -> Result<(), Error> {
+        let dir = self._cfg.snapshot_dir();
+        let paths = self._cfg.backup_paths();
+        if self._dryrun {
+            info!("Native backup: {:?} -> {}", paths, dir.display());
+            return Ok(());
+        }
+        match self.store().backup(&paths, &self._cfg.snapshot_tags()) {
+            Ok(s) => {
+                info!("Created snapshot {}.", s.id);
+                Ok(())
+            }
+            Err(e) => {
+                warning!("Backup failed: {}.", e);
+                self.backup_failed()
+            }
+        }
+    }
+
+    fn store(&self) -> Box<dyn snapshot::Store> {
+        snapshot::open(self._cfg.snapshot_dir(), self._cfg.snapshot_dedup())
+    }
+
+    fn rclone_backup_cml(&self) -> Vec<String> {
         let mut cml = vec![self._cfg.rclone_exe(), String::from("sync")];
-        cml.push(self._cfg.rclone_local().unwrap().to_owned());
-        cml.push(self._cfg.rclone_remote().unwrap().to_owned());
+        cml.push(self._cfg.rclone_local().unwrap());
+        cml.push(self._cfg.rclone_remote().unwrap());
+        if self._cfg.rclone_history() {
+            cml.push(String::from("--backup-dir"));
+            cml.push(history::dir(&self._cfg.rclone_remote().unwrap(), chrono::Local::now()));
+        }
+        cml.extend_from_slice(&self._cfg.rclone_exclude());
         cml.extend_from_slice(&self._cfg.rclone_flag());
+        cml
+    }
+
+    fn pending_path(&self) -> std::path::PathBuf {
+        pending::PendingUpload::path(&self._cfg.state_dir(), self._cfg.game())
+    }
+
+    /// Remember that the saves need be uploaded at the next start.
+    fn mark_pending(&self, reason: String) {
+        if self._dryrun {
+            return;
+        }
+        let p = pending::PendingUpload::new(self._cfg.rclone_remote().unwrap(), reason);
+        match p.save(&self.pending_path()) {
+            Ok(_) => warning!("Saves are not uploaded to {}, they will be uploaded at the next start.", p.remote),
+            Err(e) => warning!("Failed to save pending upload: {}.", e),
+        }
+    }
+
+    fn clear_pending(&self) {
+        let path = self.pending_path();
+        if path.exists() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                warning!("Failed to remove {}: {}.", path.display(), e);
+            }
+        }
+    }
+
+    /// Upload saves a previous session failed to upload.
+    fn flush_pending(&self) -> Result<Pending, Error> {
+        let p = match pending::PendingUpload::load(&self.pending_path()) {
+            Some(p) => p,
+            None => return Ok(Pending::Uploaded),
+        };
+        warning!(
+            "Saves of the session at {} were not uploaded to {} ({}), uploading them now.",
+            p.time.format("%Y-%m-%d %H:%M:%S"),
+            p.remote,
+            p.reason
+        );
+        if !self.check_sync("backup_rclone", &self._cfg.rclone_local().unwrap(), &self._cfg.rclone_remote().unwrap())? {
+            return Ok(Pending::Failed);
+        }
+        if !self._dryrun && !self.check_conflict(true, "Keep local saves and upload them over remote ones.")? {
+            self.clear_pending();
+            return Ok(Pending::Dropped);
+        }
+        let cml = self.rclone_backup_cml();
+        report::command(&cml);
         if self._dryrun {
-            println!("Rclone backup command line: {:?}", cml);
+            info!("Rclone backup command line: {:?}", cml);
+            return Ok(Pending::Uploaded);
+        }
+        let e = self.call_retry(cml, true)?;
+        report::status(&e);
+        if let ExitStatus::Exited(0) = e {
+            self.clear_pending();
+            return Ok(Pending::Uploaded);
+        }
+        warning!("Pending upload failed: {:?}.", e);
+        Ok(Pending::Failed)
+    }
+
+    /// Rename local saves in `local` so they are kept next to the ones restored from the remote.
+    fn move_aside(&self, local: &str) -> Result<(), Error> {
+        let dest = format!(
+            "{}.conflict-{}",
+            local.trim_end_matches(['/', '\\']),
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        );
+        std::fs::rename(local, &dest)?;
+        info!("Local saves moved to {}.", dest);
+        Ok(())
+    }
+
+    fn backup_rclone(&self) -> Result<(), Error> {
+        let sync = self.check_sync("backup_rclone", &self._cfg.rclone_local().unwrap(), &self._cfg.rclone_remote().unwrap());
+        if !matches!(sync, Ok(true)) {
+            self.mark_pending(String::from("skipped by sync guard"));
+            sync?;
+            return Ok(());
+        }
+        let cml = self.rclone_backup_cml();
+        report::command(&cml);
+        if self._dryrun {
+            info!("Rclone backup command line: {:?}", cml);
             Ok(())
         } else {
-            let e = Self::call(cml)?;
+            let e = self.call_retry(cml, true)?;
+            report::status(&e);
             let ok = match &e {
                 ExitStatus::Exited(c) => *c == 0,
                 _ => false,
             };
             if !ok {
-                println!("Rclone backup failed: {:?}.", e);
-                if self._rclone_enable {
-                    if !utils::ask_continue() {
-                        return Err(Error::Exited);
-                    }
+                warning!("Rclone backup failed: {:?}.", e);
+                self.mark_pending(format!("{:?}", e));
+                if self.should_continue("backup_rclone", FailurePolicy::Ask) {
                     return Ok(());
                 }
                 return Err(Error::Exited);
             }
+            self.clear_pending();
+            self.release_lock();
             Ok(())
         }
     }
 
     #[cfg(not(windows))]
     fn call(cml: Vec<String>) -> Result<ExitStatus, subprocess::PopenError> {
-        let mut p = subprocess::Popen::create(&cml, subprocess::PopenConfig::default())?;
-        p.wait()
+        Self::call_env(cml, &[])
+    }
+
+    /// Like [Self::call], with `env` added to the environment.
+    #[cfg(not(windows))]
+    fn call_env(cml: Vec<String>, env: &[(String, String)]) -> Result<ExitStatus, subprocess::PopenError> {
+        debug!("Call {:?}", cml);
+        let start = Instant::now();
+        let re = Self::popen(cml, env);
+        Self::log_status(&re, start);
+        re
+    }
+
+    /// Log the result of a command started at `start`.
+    fn log_status<E: std::fmt::Display>(re: &Result<ExitStatus, E>, start: Instant) {
+        match re {
+            Ok(e) => debug!("{:?} after {:.1}s", e, start.elapsed().as_secs_f64()),
+            Err(e) => debug!("Failed to start: {}", e),
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn popen(cml: Vec<String>, env: &[(String, String)]) -> Result<ExitStatus, subprocess::PopenError> {
+        let mut config = subprocess::PopenConfig::default();
+        if !env.is_empty() {
+            let mut vars = subprocess::PopenConfig::current_env();
+            vars.retain(|(k, _)| !env.iter().any(|(e, _)| k == e.as_str()));
+            vars.extend(env.iter().map(|(k, v)| (k.into(), v.into())));
+            config.env = Some(vars);
+        }
+        let capture = capture::current();
+        if let Some(c) = &capture {
+            c.begin(log::phase(), &cml);
+            config.stdout = subprocess::Redirection::Pipe;
+            config.stderr = subprocess::Redirection::Pipe;
+        }
+        let mut p = subprocess::Popen::create(&cml, config)?;
+        let mut tee = Vec::new();
+        if let Some(c) = &capture {
+            tee.extend(p.stdout.take().map(|o| c.tee(o, false)));
+            tee.extend(p.stderr.take().map(|e| c.tee(e, true)));
+        }
+        let re = p.wait();
+        for t in tee {
+            let _ = t.join();
+        }
+        if let Some(c) = &capture {
+            match &re {
+                Ok(e) => c.end(&format!("{:?}", e)),
+                Err(e) => c.end(&e.to_string()),
+            }
+        }
+        re
     }
 
     #[cfg(windows)]
     fn call(cml: Vec<String>) -> Result<ExitStatus, windows::PopenError> {
-        let t = Vec::<String>::new();
-        windows::call(&cml, &t, None::<String>).map(|c| ExitStatus::Exited(c))
+        Self::call_env(cml, &[])
+    }
+
+    #[cfg(windows)]
+    fn call_env(cml: Vec<String>, env: &[(String, String)]) -> Result<ExitStatus, windows::PopenError> {
+        debug!("Call {:?}", cml);
+        let start = Instant::now();
+        let re = Self::popen(&cml, &[], None, env);
+        Self::log_status(&re, start);
+        re
     }
 
     #[cfg(windows)]
     fn call2(cml: Vec<String>, dlls: Vec<String>, cdir: Option<String>) -> Result<ExitStatus, windows::PopenError> {
-        windows::call(&cml, &dlls, cdir).map(|c| ExitStatus::Exited(c))
+        debug!("Call {:?} with dlls {:?} in {:?}", cml, dlls, cdir);
+        let start = Instant::now();
+        let re = Self::popen(&cml, &dlls, cdir, &[]);
+        Self::log_status(&re, start);
+        re
+    }
+
+    #[cfg(windows)]
+    fn popen(
+        cml: &[String],
+        dlls: &[String],
+        cdir: Option<String>,
+        env: &[(String, String)],
+    ) -> Result<ExitStatus, windows::PopenError> {
+        let capture = capture::current();
+        if let Some(c) = &capture {
+            c.begin(log::phase(), cml);
+        }
+        let re = windows::call(cml, dlls, cdir, env, capture.as_ref()).map(ExitStatus::Exited);
+        if let Some(c) = &capture {
+            match &re {
+                Ok(e) => c.end(&format!("{:?}", e)),
+                Err(e) => c.end(&e.to_string()),
+            }
+        }
+        re
+    }
+
+    /// Call `cml` and retry it with increasing delays as configured in `retry`.
+    fn call_retry(&self, cml: Vec<String>, rclone: bool) -> Result<ExitStatus, Error> {
+        let retry = self._cfg.retry(rclone);
+        let mut delay = retry.delay;
+        let mut attempt = 0;
+        loop {
+            let e = Self::call(cml.clone())?;
+            let code = match e {
+                ExitStatus::Exited(0) => return Ok(e),
+                ExitStatus::Exited(c) => c,
+                _ => return Ok(e),
+            };
+            let retryable = retry.exit_codes.is_empty() || retry.exit_codes.contains(&code);
+            if !retryable || attempt >= retry.attempts {
+                if attempt > 0 {
+                    warning!("{} failed after {} attempts.", cml[0], attempt + 1);
+                }
+                return Ok(e);
+            }
+            attempt += 1;
+            warning!(
+                "{} exited with {}, retry {}/{} in {}s.",
+                cml[0],
+                code,
+                attempt,
+                retry.attempts,
+                delay.as_secs_f64()
+            );
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(retry.max_delay);
+        }
+    }
+
+    /// Run the hook `name` of `phase`. `result` is the result of the phase for `post_` hooks,
+    /// or of the previous phase otherwise.
+    fn hook(&self, name: &str, phase: &str, result: &str) -> Result<(), Error> {
+        let hook = match self._cfg.hook(name) {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+        if self._dryrun {
+            info!("Hook {} command line: {:?}", name, hook.command);
+            return Ok(());
+        }
+        let env = [
+            (String::from("GAME_AUTO_SYNC_GAME"), self._cfg.game().to_owned()),
+            (String::from("GAME_AUTO_SYNC_HOOK"), name.to_owned()),
+            (String::from("GAME_AUTO_SYNC_PHASE"), phase.to_owned()),
+            (String::from("GAME_AUTO_SYNC_RESULT"), result.to_owned()),
+        ];
+        let e = Self::call_env(hook.command.clone(), &env)?;
+        let ok = match &e {
+            ExitStatus::Exited(c) => *c == 0,
+            _ => false,
+        };
+        if !ok {
+            warning!("Hook {} failed: {:?}.", name, e);
+            if hook.abort_on_failure {
+                return Err(Error::Exited);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `f` between the `pre_` and `post_` hooks of `phase`, or the `on_error` hook if anything failed.
+    /// `result` is updated with the result of this phase.
+    fn phase<F: FnOnce() -> Result<(), Error>>(&self, phase: &'static str, result: &mut &'static str, f: F) -> Result<(), Error> {
+        log::set_phase(phase);
+        let capture = capture::current();
+        if let Some(c) = &capture {
+            c.clear_tail();
+        }
+        let start = Instant::now();
+        let re = self.hook(&format!("pre_{}", phase), phase, result).and_then(|_| f());
+        *result = if re.is_ok() { "success" } else { "failure" };
+        let re = re.and_then(|_| self.hook(&format!("post_{}", phase), phase, result));
+        if re.is_err() {
+            *result = "failure";
+            let _ = self.hook("on_error", phase, result);
+        }
+        debug!("Phase {} finished with {} in {:.1}s", phase, result, start.elapsed().as_secs_f64());
+        log::set_phase("main");
+        match (re, capture.map(|c| c.tail())) {
+            (Err(e), Some(tail)) if !tail.is_empty() => Err(Error::Output(phase, Box::new(e), tail)),
+            (re, _) => re,
+        }
+    }
+
+    /// Delete snapshots which are not selected by the retention policy.
+    fn prune(&self) -> Result<(), snapshot::SnapshotError> {
+        let policy = match self._cfg.retention() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let store = self.store();
+        let snapshots = store.list()?;
+        let keep = snapshot::retention::select(&policy, &snapshots);
+        let remove: Vec<_> = snapshots
+            .iter()
+            .zip(keep)
+            .filter_map(|(s, k)| if k { None } else { Some(s) })
+            .collect();
+        if remove.is_empty() {
+            info!("No snapshot need to be pruned.");
+            return Ok(());
+        }
+        for s in remove.iter() {
+            let action = if self._dryrun { "Would remove" } else { "Remove" };
+            info!("{} snapshot {} ({}).", action, s.id, s.time.format("%Y-%m-%d %H:%M:%S"));
+        }
+        if !self._dryrun {
+            store.remove(&remove)?;
+        }
+        Ok(())
     }
 
     fn restore(&self) -> Result<(), Error> {
+        if self._cfg.backup_engine() == cfg::BackupEngine::Native {
+            return self.restore_native();
+        }
         let cml = match self._cfg.restore_command() {
             Some(cml) => cml,
-            None => {
-                let mut def = vec![self._cfg.game_backuper_exe()];
-                if let Some(cfg_path) = self._cfg.game_backuper_cfg() {
-                    def.push(String::from("-c"));
-                    def.push(cfg_path);
-                }
-                def.push(String::from("restore"));
-                def
-            }
+            None => self.default_restore_cml(),
         };
+        // An argument which is only the placeholder is dropped if nothing is selected.
+        let from = self._restore_from.clone().unwrap_or_default();
+        let cml: Vec<_> = cml
+            .into_iter()
+            .filter(|a| !(a == "{restore_from}" && from.is_empty()))
+            .map(|a| a.replace("{restore_from}", &from))
+            .collect();
+        report::command(&cml);
         if self._dryrun {
-            println!("Restore command line: {:?}", cml);
+            info!("Restore command line: {:?}", cml);
             Ok(())
         } else {
-            let e = Self::call(cml)?;
+            let e = self.call_retry(cml, false)?;
+            report::status(&e);
             let ok = match &e {
                 ExitStatus::Exited(c) => *c == 0,
                 _ => false,
             };
             if !ok {
-                println!("Restore failed: {:?}.", e);
-                if !utils::ask_continue() {
+                warning!("Restore failed: {:?}.", e);
+                if !self.should_continue("restore", FailurePolicy::Ask) {
                     return Err(Error::Exited);
                 }
             }
@@ -159,53 +698,730 @@ impl Main {
         }
     }
 
-    fn restore_rclone(&self) -> Result<(), Error> {
-        let mut cml = vec![self._cfg.rclone_exe(), String::from("sync")];
-        cml.push(self._cfg.rclone_remote().unwrap().to_owned());
-        cml.push(self._cfg.rclone_local().unwrap().to_owned());
-        cml.extend_from_slice(&self._cfg.rclone_flag());
-        if self._dryrun {
-            println!("Rclone restore command line: {:?}", cml);
-            Ok(())
+    fn restore_native(&self) -> Result<(), Error> {
+        let dir = self._cfg.snapshot_dir();
+        let paths = self._cfg.backup_paths();
+        let store = self.store();
+        let selection = self.selection(RestoreFrom::Native);
+        let re = match &selection {
+            Some(sel) => store.list().and_then(|mut l| match sel.pick(&l, |s| &s.id, |s| s.time) {
+                Some(i) => Ok(Some(l.swap_remove(i))),
+                None => Err(snapshot::SnapshotError::NotFound(sel.to_string())),
+            }),
+            None => store.latest(),
+        };
+        let re = re.and_then(|s| match s {
+            Some(s) => {
+                if self._dryrun {
+                    info!("Native restore: {} -> {:?}", s.id, paths);
+                    return Ok(());
+                }
+                store.restore(&s, &paths)?;
+                info!("Restored snapshot {}.", s.id);
+                Ok(())
+            }
+            None => {
+                info!("No snapshot found in {}, skip restore.", dir.display());
+                Ok(())
+            }
+        });
+        if let Err(e) = re {
+            warning!("Restore failed: {}.", e);
+            if !self.should_continue("restore", FailurePolicy::Ask) {
+                return Err(Error::Exited);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that syncing `source` to `dest` in `phase` will not wipe `dest`, and ask by the failure policy
+    /// of `phase` if it might. Returns false if the sync should be skipped.
+    fn check_sync(&self, phase: &str, source: &str, dest: &str) -> Result<bool, Error> {
+        if self.sync_is_safe(source, dest) {
+            return Ok(true);
+        }
+        let choices = [("s", "Skip syncing and keep files in the destination."), ("c", "Sync anyway.")];
+        Ok(self.choose(phase, &choices, 0)? == 1)
+    }
+
+    /// Whether syncing `source` to `dest` does not delete too many files, without asking anything.
+    /// A sync which can not be checked is not safe.
+    fn sync_is_safe(&self, source: &str, dest: &str) -> bool {
+        if self._dryrun || !self._cfg.sync_guard() {
+            return true;
+        }
+        let exe = self._cfg.rclone_exe();
+        let exclude = self._cfg.rclone_exclude();
+        let listing = rclone::lsjson(&exe, source, false, &exclude)
+            .and_then(|s| Ok((s, rclone::lsjson(&exe, dest, false, &exclude)?)));
+        match rclone::sync_risk(&listing, self._cfg.sync_max_delete()) {
+            None => return true,
+            Some(rclone::SyncRisk::Unchecked(e)) => {
+                warning!("Failed to check files before syncing {} to {}: {}.", source, dest, e);
+            }
+            Some(rclone::SyncRisk::Wipe(n)) => {
+                warning!("{} is empty or missing, syncing it to {} would delete all {} files there.", source, dest, n);
+            }
+            Some(rclone::SyncRisk::TooMany { deletes, total }) => {
+                warning!(
+                    "Syncing {} to {} would delete {} of {} files there, more than {}%:",
+                    source,
+                    dest,
+                    deletes.len(),
+                    total,
+                    self._cfg.sync_max_delete()
+                );
+                for f in deletes.iter().take(10) {
+                    warning!("  {}", f);
+                }
+                if deletes.len() > 10 {
+                    warning!("  ... and {} more.", deletes.len() - 10);
+                }
+            }
+        }
+        false
+    }
+
+    /// Compare local and remote saves before uploading (`upload`) or downloading them, as the copy may
+    /// overwrite changes made since on the other side. If it does, ask whether to keep the local saves,
+    /// described by `keep_local`, take the remote ones or keep both. Returns false to skip the copy.
+    fn check_conflict(&self, upload: bool, keep_local: &str) -> Result<bool, Error> {
+        let exe = self._cfg.rclone_exe();
+        let local = self._cfg.rclone_local().unwrap();
+        let remote = self._cfg.rclone_remote().unwrap();
+        let exclude = self._cfg.rclone_exclude();
+        let re = rclone::lsjson(&exe, &local, true, &exclude)
+            .and_then(|l| Ok((l, rclone::lsjson(&exe, &remote, true, &exclude)?)));
+        let (l, r) = match re {
+            Ok(re) => re,
+            Err(e) => {
+                warning!("Failed to compare local and remote saves: {}.", e);
+                return Ok(true);
+            }
+        };
+        // Files the copy would overwrite or delete.
+        let c = if upload {
+            rclone::find_conflict(&r, &l)
         } else {
-            let e = Self::call(cml)?;
+            rclone::find_conflict(&l, &r)
+        };
+        if c.is_empty() {
+            return Ok(true);
+        }
+        if upload {
+            warning!("Remote saves in {} changed since the saves in {} were made.", remote, local);
+        } else if c.diverged() {
+            warning!("Local saves in {} and remote saves in {} have diverged.", local, remote);
+        } else {
+            info!("Local saves in {} are newer than remote saves in {}.", local, remote);
+        }
+        let files: Vec<_> = c.local_newer.iter().chain(c.local_only.iter()).collect();
+        for f in files.iter().take(10) {
+            info!("  {}", f);
+        }
+        if files.len() > 10 {
+            info!("  ... and {} more.", files.len() - 10);
+        }
+        let choices = [
+            ("l", keep_local),
+            ("r", "Take remote saves and overwrite local ones."),
+            ("b", "Keep both. Move local saves aside, then take remote saves."),
+        ];
+        let phase = if upload { "backup_rclone" } else { "restore_rclone" };
+        match self.choose(phase, &choices, 2)? {
+            0 => Ok(upload),
+            1 => Ok(!upload),
+            _ => {
+                self.move_aside(&local)?;
+                Ok(!upload)
+            }
+        }
+    }
+
+    /// Download the remote saves, after checking for local changes if `conflict_check` is set.
+    fn restore_rclone(&self, conflict_check: bool) -> Result<(), Error> {
+        if !self.check_sync("restore_rclone", &self._cfg.rclone_remote().unwrap(), &self._cfg.rclone_local().unwrap())? {
+            warning!("Skipped restoring from remote.");
+            return Ok(());
+        }
+        if !self._dryrun
+            && conflict_check
+            && self._cfg.conflict_check()
+            && !self.check_conflict(false, "Keep local saves and skip restoring from remote.")?
+        {
+            return Ok(());
+        }
+        let cmls = match self.selection(RestoreFrom::History) {
+            Some(sel) => {
+                let remote = self._cfg.rclone_remote().unwrap();
+                let entries = history::list(&self._cfg.rclone_exe(), &remote)?;
+                match history::start(&entries, &sel) {
+                    Some(i) => {
+                        if let Some(e) = entries.get(i) {
+                            info!(
+                                "Restore saves from {}, before the upload at {}. Files created since then are kept.",
+                                sel,
+                                e.name
+                            );
+                        }
+                        self.history_restore_cmls(&entries[i..])
+                    }
+                    None => {
+                        warning!("No history of {} matches {}.", remote, sel);
+                        if !self.should_continue("restore_rclone", FailurePolicy::Ask) {
+                            return Err(Error::Exited);
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+            None => self.history_restore_cmls(&[]),
+        };
+        for cml in cmls {
+            report::command(&cml);
+            if self._dryrun {
+                info!("Rclone restore command line: {:?}", cml);
+                continue;
+            }
+            let e = self.call_retry(cml, true)?;
+            report::status(&e);
             let ok = match &e {
                 ExitStatus::Exited(c) => *c == 0,
                 _ => false,
             };
             if !ok {
-                println!("Rclone restore failed: {:?}.", e);
-                if !utils::ask_continue() {
+                warning!("Rclone restore failed: {:?}.", e);
+                if !self.should_continue("restore_rclone", FailurePolicy::Ask) {
                     return Err(Error::Exited);
                 }
+                break;
             }
-            Ok(())
         }
+        Ok(())
+    }
+
+    /// Print the history folders of the remote.
+    fn history_list(&self) -> Result<(), Error> {
+        let remote = self._cfg.rclone_remote().unwrap();
+        let entries = history::list(&self._cfg.rclone_exe(), &remote)?;
+        if entries.is_empty() {
+            println!("No history in {}.", history::base(&remote));
+            return Ok(());
+        }
+        println!("{:<16}  {:<19}  {:>6}  {:>10}", "NAME", "UPLOADED", "FILES", "SIZE");
+        for e in entries {
+            println!(
+                "{:<16}  {:<19}  {:>6}  {:>10}",
+                e.name,
+                e.time.format("%Y-%m-%d %H:%M:%S"),
+                e.files,
+                utils::format_size(e.size)
+            );
+        }
+        Ok(())
+    }
+
+    /// Commands to download the remote saves and undo the uploads of `entries` in `rclone_local`.
+    fn history_restore_cmls(&self, entries: &[history::Entry]) -> Vec<Vec<String>> {
+        history::restore_cmls(
+            &self._cfg.rclone_exe(),
+            &self._cfg.rclone_remote().unwrap(),
+            &self._cfg.rclone_local().unwrap(),
+            entries,
+            &self._cfg.rclone_exclude(),
+            &self._cfg.rclone_flag(),
+        )
+    }
+
+    /// Rebuild the saves as they were before the upload `name` in `rclone_local`,
+    /// and queue them for upload at the next start.
+    fn history_restore(&self, name: &str) -> Result<(), Error> {
+        let exe = self._cfg.rclone_exe();
+        let local = self._cfg.rclone_local().unwrap();
+        let remote = self._cfg.rclone_remote().unwrap();
+        let entries = history::list(&exe, &remote)?;
+        let start = match entries.iter().position(|e| e.name == name) {
+            Some(i) => i,
+            None => {
+                error!("{} is not in the history of {}.", name, remote);
+                return Err(Error::Exited);
+            }
+        };
+        let cmls = self.history_restore_cmls(&entries[start..]);
+        if self._dryrun {
+            for c in cmls {
+                info!("Rclone history restore command line: {:?}", c);
+            }
+            return Ok(());
+        }
+        warning!(
+            "This overwrites saves in {} with the ones before the upload at {}. Files created since then are kept.",
+            local,
+            name
+        );
+        if !self.should_continue("restore_rclone", FailurePolicy::Ask) {
+            return Err(Error::Exited);
+        }
+        for c in cmls {
+            report::command(&c);
+            let e = self.call_retry(c, true)?;
+            report::status(&e);
+            if !matches!(e, ExitStatus::Exited(0)) {
+                warning!("Rclone history restore failed: {:?}.", e);
+                return Err(Error::Exited);
+            }
+        }
+        self.mark_pending(format!("restored from history {}", name));
+        Ok(())
+    }
+
+    /// Remove history folders by `rclone_history_keep` and `rclone_history_max_age`.
+    fn history_prune(&self) -> Result<(), Error> {
+        let keep = self._cfg.rclone_history_keep();
+        let max_age = self._cfg.rclone_history_max_age();
+        if keep.is_none() && max_age.is_none() {
+            error!("rclone_history_keep or rclone_history_max_age need be set to prune history.");
+            return Err(Error::Exited);
+        }
+        let exe = self._cfg.rclone_exe();
+        let remote = self._cfg.rclone_remote().unwrap();
+        let entries = history::list(&exe, &remote)?;
+        let remove = history::select_prune(&entries, keep, max_age);
+        if remove.is_empty() {
+            info!("No history need to be pruned.");
+            return Ok(());
+        }
+        for e in remove {
+            let action = if self._dryrun { "Would remove" } else { "Remove" };
+            info!("{} history {} ({} files).", action, e.name, e.files);
+            if !self._dryrun {
+                rclone::purge(&exe, &history::dir(&remote, e.time))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print local snapshots and remote saves, or write them as JSON to `json` if given.
+    fn list<W: Write + ?Sized>(&self, json: Option<&mut W>) -> Result<(), Error> {
+        let mut backups = Vec::new();
+        if self._cfg.backup_engine() == cfg::BackupEngine::Native {
+            for s in self.store().list()? {
+                backups.push(list::Backup {
+                    location: "local",
+                    id: s.id,
+                    time: Some(s.time),
+                    size: s.size,
+                    files: s.files,
+                    host: Some(s.host).filter(|h| !h.is_empty()),
+                    tags: s.tags,
+                });
+            }
+        } else if json.is_none() {
+            info!("Local snapshots are only listed when backup_engine is native.");
+        }
+        if self._rclone_enable {
+            let exe = self._cfg.rclone_exe();
+            let remote = self._cfg.rclone_remote().unwrap();
+            let items = rclone::lsjson(&exe, &remote, false, &self._cfg.rclone_exclude())?;
+            backups.push(list::Backup {
+                location: "remote",
+                time: items.iter().map(|i| i.mod_time.with_timezone(&chrono::Local)).max(),
+                size: items.iter().map(|i| i.size.max(0) as u64).sum(),
+                files: items.len(),
+                id: remote.clone(),
+                host: None,
+                tags: Vec::new(),
+            });
+            if self._cfg.rclone_history() {
+                for e in history::list(&exe, &remote)? {
+                    backups.push(list::Backup {
+                        location: "history",
+                        id: e.name,
+                        time: Some(e.time),
+                        size: e.size.max(0) as u64,
+                        files: e.files,
+                        host: None,
+                        tags: Vec::new(),
+                    });
+                }
+            }
+        }
+        if let Some(out) = json {
+            writeln!(out, "{}", serde_json::to_string_pretty(&backups).unwrap())?;
+        } else {
+            list::print_table(&backups);
+        }
+        Ok(())
+    }
+
+    /// Print the state of local and remote saves.
+    fn status(&self) -> Result<(), Error> {
+        println!("Game: {}", self._cfg.game());
+        if self._cfg.backup_engine() == cfg::BackupEngine::Native {
+            let dir = self._cfg.snapshot_dir();
+            let list = self.store().list()?;
+            match list.last() {
+                Some(s) => println!(
+                    "Snapshots: {} in {}, latest {} from {} at {}",
+                    list.len(),
+                    dir.display(),
+                    s.id,
+                    s.host,
+                    s.time.format("%Y-%m-%d %H:%M:%S")
+                ),
+                None => println!("Snapshots: none in {}", dir.display()),
+            }
+        } else {
+            println!("Backup: external commands");
+        }
+        if !self._rclone_enable {
+            println!("Remote: not set");
+            return Ok(());
+        }
+        let exe = self._cfg.rclone_exe();
+        let remote = self._cfg.rclone_remote().unwrap();
+        println!("Remote: {} <-> {}", self._cfg.rclone_local().unwrap(), remote);
+        if self._cfg.session_lock() {
+            let path = self._cfg.lock_path().unwrap();
+            match rclone::cat(&exe, &path) {
+                Ok(Some(s)) => match lock::SessionLock::parse(&s) {
+                    Some(l) => println!(
+                        "Session lock: held by {} (PID {}) since {}{}",
+                        l.host,
+                        l.pid,
+                        l.time.format("%Y-%m-%d %H:%M:%S"),
+                        if l.is_own() {
+                            ", this machine"
+                        } else if l.is_expired(self._cfg.lock_expire()) {
+                            ", expired"
+                        } else {
+                            ""
+                        }
+                    ),
+                    None => println!("Session lock: invalid lock in {}", path),
+                },
+                Ok(None) => println!("Session lock: free"),
+                Err(e) => println!("Session lock: unknown, {}", e),
+            }
+        }
+        match pending::PendingUpload::load(&self.pending_path()) {
+            Some(p) => println!(
+                "Pending upload: saves of {} ({})",
+                p.time.format("%Y-%m-%d %H:%M:%S"),
+                p.reason
+            ),
+            None => println!("Pending upload: none"),
+        }
+        if self._cfg.rclone_history() {
+            match history::list(&exe, &remote) {
+                Ok(h) => match h.last() {
+                    Some(e) => println!("History: {} uploads, latest {}", h.len(), e.name),
+                    None => println!("History: empty"),
+                },
+                Err(e) => println!("History: unknown, {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the differences between local and remote saves.
+    fn diff(&self) -> Result<(), Error> {
+        let exe = self._cfg.rclone_exe();
+        let local = self._cfg.rclone_local().unwrap();
+        let remote = self._cfg.rclone_remote().unwrap();
+        let exclude = self._cfg.rclone_exclude();
+        let l = rclone::lsjson(&exe, &local, true, &exclude)?;
+        let r = rclone::lsjson(&exe, &remote, true, &exclude)?;
+        let c = rclone::find_conflict(&l, &r);
+        let mut rows: Vec<(&str, &str)> = Vec::new();
+        rows.extend(c.local_only.iter().map(|p| ("local only", p.as_str())));
+        rows.extend(rclone::sync_deletes(&l, &r).into_iter().map(|p| ("remote only", p)));
+        rows.extend(c.local_newer.iter().map(|p| ("local newer", p.as_str())));
+        rows.extend(c.remote_newer.iter().map(|p| ("remote newer", p.as_str())));
+        if rows.is_empty() {
+            println!("Local saves in {} and remote saves in {} are the same.", local, remote);
+            return Ok(());
+        }
+        rows.sort_by(|a, b| a.1.cmp(b.1));
+        for (k, p) in rows.iter() {
+            println!("{:<13} {}", k, p);
+        }
+        println!("{} files differ between {} and {}.", rows.len(), local, remote);
+        Ok(())
+    }
+
+    /// Check the config and the tools it uses, printing a line for each check.
+    fn doctor(&self) -> Result<(), Error> {
+        let failed = std::cell::Cell::new(0);
+        let check = |level: &str, msg: String| {
+            if level == "fail" {
+                failed.set(failed.get() + 1);
+            }
+            println!("[{:<4}] {}", level, msg);
+        };
+        let exe = |what: &str, name: &str| match utils::find_exe(name) {
+            Some(p) => check("ok", format!("{} {} found at {}.", what, name, p.display())),
+            None => check("fail", format!("{} {} not found.", what, name)),
+        };
+        match self._cfg.game_exe() {
+            Some(cml) if !cml.is_empty() => exe("game_exe", &cml[0]),
+            _ => check("warn", String::from("game_exe is not set, only backup and restore are available.")),
+        }
+        if self._cfg.backup_engine() == cfg::BackupEngine::Native {
+            for p in self._cfg.backup_paths() {
+                if std::path::Path::new(&p).exists() {
+                    check("ok", format!("Backup path {} exists.", p));
+                } else {
+                    check("warn", format!("Backup path {} does not exist.", p));
+                }
+            }
+            let dir = self._cfg.snapshot_dir();
+            match utils::check_writable(&dir) {
+                Ok(_) => check("ok", format!("snapshot_dir {} is writable.", dir.display())),
+                Err(e) => check("fail", format!("snapshot_dir {} is not writable: {}.", dir.display(), e)),
+            }
+        } else {
+            let backup = self._cfg.backup_command().unwrap_or_else(|| self.default_backup_cml());
+            if let Some(e) = backup.first() {
+                exe("Backup command", e);
+            }
+            if let Some(e) = self._cfg.restore_command().as_ref().and_then(|c| c.first()) {
+                exe("Restore command", e);
+            }
+        }
+        if self._rclone_enable {
+            let rclone = self._cfg.rclone_exe();
+            match rclone::version(&rclone) {
+                Ok(v) => check("ok", format!("rclone_exe {} works: {}.", rclone, v)),
+                Err(e) => check("fail", format!("rclone_exe {} does not work: {}.", rclone, e)),
+            }
+            let local = self._cfg.rclone_local().unwrap();
+            if std::path::Path::new(&local).is_dir() {
+                check("ok", format!("rclone_local {} exists.", local));
+            } else {
+                check("warn", format!("rclone_local {} does not exist.", local));
+            }
+            let remote = self._cfg.rclone_remote().unwrap();
+            match rclone::lsjson(&rclone, &remote, false, &self._cfg.rclone_exclude()) {
+                Ok(l) => check("ok", format!("rclone_remote {} is reachable, {} files.", remote, l.len())),
+                Err(e) => check("fail", format!("rclone_remote {} is not reachable: {}.", remote, e)),
+            }
+        }
+        let dir = self._cfg.state_dir();
+        match utils::check_writable(&dir) {
+            Ok(_) => check("ok", format!("state_dir {} is writable.", dir.display())),
+            Err(e) => check("fail", format!("state_dir {} is not writable: {}.", dir.display(), e)),
+        }
+        if failed.get() > 0 {
+            println!("{} problems found.", failed.get());
+            return Err(Error::Exited);
+        }
+        Ok(())
     }
 
     fn run(&self) -> Result<(), Error> {
+        if !self._run_only && !self._backup_only && !self._restore_only && self._rclone_enable && self._cfg.session_lock()
+        {
+            self.acquire_lock()?;
+        }
+        // The result of the last phase, passed to hooks.
+        let mut result = "none";
         if !self._run_only && !self._skip_restore && !self._backup_only {
-            if self._rclone_enable {
-                self.restore_rclone()?;
+            let re = self.phase("restore", &mut result, || {
+                if self.rclone_enabled() {
+                    match report::phase("pending_upload", || self.flush_pending())? {
+                        Pending::Uploaded => report::phase("restore_rclone", || self.restore_rclone(true))?,
+                        Pending::Dropped => report::phase("restore_rclone", || self.restore_rclone(false))?,
+                        Pending::Failed => {
+                            warning!("Local saves are newer than remote saves, skip downloading them.")
+                        }
+                    }
+                }
+                report::phase("restore", || self.restore())
+            });
+            if re.is_err() {
+                self.release_lock();
             }
-            self.restore()?;
+            re?;
+        }
+        if self._restore_only {
+            return Ok(());
         }
         if self._run_only || !self._backup_only {
-            self.run_exe()?;
+            self.phase("run", &mut result, || {
+                let interval = self._cfg.autosave_interval();
+                report::phase("run_exe", || {
+                    if !self._run_only && (interval.is_some() || self._cfg.watch_saves()) {
+                        self.run_with_autosave(interval)
+                    } else {
+                        self.run_exe()
+                    }
+                })
+            })?;
         }
         if !self._run_only {
-            self.backup()?;
-            if self._rclone_enable {
-                self.backup_rclone()?;
+            self.phase("backup", &mut result, || {
+                report::phase("backup", || self.backup())?;
+                if self._cfg.backup_engine() == cfg::BackupEngine::Native {
+                    if let Err(e) = report::phase("prune", || self.prune()) {
+                        warning!("Prune failed: {}.", e);
+                    }
+                }
+                Ok(())
+            })?;
+            if self.rclone_enabled() {
+                self.phase("upload", &mut result, || report::phase("backup_rclone", || self.backup_rclone()))?;
+            } else if self._rclone_enable {
+                self.mark_pending(String::from("played offline"));
             }
         }
         Ok(())
     }
 
+    /// Run the game while backing up in a background thread,
+    /// every `interval` and after the saves are changed if `watch_saves` is enabled.
+    fn run_with_autosave(&self, interval: Option<Duration>) -> Result<(), Error> {
+        let watch = self._cfg.watch_saves();
+        if self._dryrun {
+            if let Some(interval) = interval {
+                info!("Autosave every {:?}", interval);
+            }
+            if watch {
+                info!("Watch saves: {:?}", self._cfg.watch_paths());
+            }
+            return self.run_exe();
+        }
+        let (tx, rx) = mpsc::channel();
+        let _watcher = if watch {
+            watch::watch(&self._cfg.watch_paths(), tx.clone())
+        } else {
+            None
+        };
+        std::thread::scope(|s| {
+            s.spawn(|| self.autosave_loop(interval, rx));
+            let re = self.run_exe();
+            let _ = tx.send(watch::Signal::Stop);
+            re
+        })
+    }
+
+    fn autosave_loop(&self, interval: Option<Duration>, rx: mpsc::Receiver<watch::Signal>) {
+        let debounce = self._cfg.watch_debounce();
+        let mut next = interval.map(|i| Instant::now() + i);
+        // When to back up after the last change of the saves.
+        let mut changed: Option<Instant> = None;
+        loop {
+            let msg = match next.into_iter().chain(changed).min() {
+                Some(d) => rx.recv_timeout(d.saturating_duration_since(Instant::now())),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match msg {
+                Ok(watch::Signal::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                Ok(watch::Signal::Changed) => changed = Some(Instant::now() + debounce),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            let now = Instant::now();
+            let by_watch = changed.is_some_and(|c| c <= now);
+            let by_interval = next.is_some_and(|n| n <= now);
+            if !by_watch && !by_interval {
+                continue;
+            }
+            if by_watch {
+                info!("Saves changed.");
+            }
+            changed = None;
+            self.autosave();
+            if let (Some(n), Some(i)) = (next.as_mut(), interval) {
+                // Autosaves which would overlap with the one that just finished are skipped.
+                let now = Instant::now();
+                *n += i;
+                let mut skipped = 0;
+                while *n <= now {
+                    *n += i;
+                    skipped += 1;
+                }
+                if skipped > 0 {
+                    warning!("Autosave: skipped {} run(s) because the previous one took too long.", skipped);
+                }
+            }
+        }
+    }
+
+    /// Back up and optionally upload without asking anything, the result is only printed.
+    fn autosave(&self) {
+        let start = Instant::now();
+        info!("Autosave started at {}.", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"));
+        if let Err(e) = self.autosave_backup() {
+            warning!("Autosave failed: {}.", e);
+            return;
+        }
+        if self._cfg.autosave_upload() && self.rclone_enabled() {
+            if !self.sync_is_safe(&self._cfg.rclone_local().unwrap(), &self._cfg.rclone_remote().unwrap()) {
+                warning!("Autosave upload skipped by the sync guard.");
+                self.mark_pending(String::from("autosave skipped by sync guard"));
+                return;
+            }
+            let reason = match Self::call(self.rclone_backup_cml()) {
+                Ok(ExitStatus::Exited(0)) => None,
+                Ok(e) => Some(format!("{:?}", e)),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(r) = reason {
+                warning!("Autosave upload failed: {}.", r);
+                self.mark_pending(format!("autosave {}", r));
+                return;
+            }
+            self.clear_pending();
+        }
+        info!("Autosave finished in {:.1}s.", start.elapsed().as_secs_f64());
+    }
+
+    fn autosave_backup(&self) -> Result<(), String> {
+        if self._cfg.backup_engine() == cfg::BackupEngine::Native {
+            let s = self
+                .store()
+                .backup(&self._cfg.backup_paths(), &self._cfg.snapshot_tags())
+                .map_err(|e| e.to_string())?;
+            info!("Created snapshot {}.", s.id);
+            if let Err(e) = self.prune() {
+                warning!("Prune failed: {}.", e);
+            }
+            return Ok(());
+        }
+        let cml = match self._cfg.backup_command() {
+            Some(cml) => cml,
+            None => self.default_backup_cml(),
+        };
+        match Self::call(cml) {
+            Ok(ExitStatus::Exited(0)) => Ok(()),
+            Ok(e) => Err(format!("{:?}", e)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Returns false if the process was still running at the timeout.
+    fn wait_for_process(&self, w: &cfg::WaitForProcess) -> bool {
+        match process::wait_for(&w.matcher, w.appear_timeout, w.timeout) {
+            process::WaitResult::Exited => true,
+            process::WaitResult::NotFound => {
+                warning!("Process {} not found in {:?}.", w.matcher, w.appear_timeout);
+                true
+            }
+            process::WaitResult::TimedOut => {
+                warning!("Process {} is still running after {:?}.", w.matcher, w.timeout.unwrap_or_default());
+                false
+            }
+        }
+    }
+
     fn run_exe(&self) -> Result<(), Error> {
         let cml = self._cfg.game_exe().unwrap();
+        report::command(&cml);
         if self._dryrun {
-            println!("Run command line: {:?}", cml);
+            info!("Run command line: {:?}", cml);
+            if let Some(w) = self._cfg.wait_for_process() {
+                info!("Wait for process: {}", w.matcher);
+            }
             Ok(())
         } else {
             #[cfg(windows)]
@@ -218,23 +1434,55 @@ impl Main {
             };
             #[cfg(windows)]
             if need_hide && !hide {
-                println!("Failed to hide console window.");
+                warning!("Failed to hide console window.");
             }
-            #[cfg(not(windows))]
+            #[cfg(target_os = "linux")]
+            let e = if self._cfg.track_process_tree() {
+                debug!("Call {:?} and wait for its process tree", cml);
+                let start = Instant::now();
+                let capture = capture::current();
+                if let Some(c) = &capture {
+                    c.begin(log::phase(), &cml);
+                }
+                let re = linux::call(&cml, capture.as_ref());
+                Self::log_status(&re, start);
+                if let Some(c) = &capture {
+                    match &re {
+                        Ok(e) => c.end(&format!("{:?}", e)),
+                        Err(e) => c.end(&e.to_string()),
+                    }
+                }
+                re?
+            } else {
+                Self::call(cml)?
+            };
+            #[cfg(all(not(windows), not(target_os = "linux")))]
             let e = Self::call(cml)?;
             #[cfg(windows)]
             let e = Self::call2(cml, self._cfg.hook_dll(), self._cfg.current_dir())?;
+            let waited = match self._cfg.wait_for_process() {
+                Some(w) => self.wait_for_process(w),
+                None => true,
+            };
             #[cfg(windows)]
             if hide {
                 windows::show_window();
             }
+            report::status(&e);
             let ok = match &e {
                 ExitStatus::Exited(c) => *c == 0,
                 _ => false,
             };
-            if !ok {
-                println!("Run failed: {:?}.", e);
-                if !self._cfg.continue_when_run_failed() && !utils::ask_continue() {
+            if !ok || !waited {
+                if !ok {
+                    warning!("Run failed: {:?}.", e);
+                }
+                let default = if self._cfg.continue_when_run_failed() {
+                    FailurePolicy::Continue
+                } else {
+                    FailurePolicy::Ask
+                };
+                if !self.should_continue("run_exe", default) {
                     return Err(Error::Exited);
                 }
             }
@@ -243,58 +1491,173 @@ impl Main {
     }
 }
 
+/// The original stdout for `--json`, all other output goes to stderr so the JSON can be parsed.
+fn json_stdout() -> Box<dyn Write> {
+    #[cfg(target_os = "linux")]
+    let re = linux::take_stdout();
+    #[cfg(windows)]
+    let re = windows::take_stdout();
+    #[cfg(not(any(target_os = "linux", windows)))]
+    let re: std::io::Result<std::fs::File> = Err(std::io::ErrorKind::Unsupported.into());
+    match re {
+        Ok(f) => Box::new(f),
+        Err(_) => Box::new(std::io::stdout()),
+    }
+}
+
 fn main() -> ExitCode {
     let argv: Vec<String> = std::env::args().collect();
-    let mut opts = Options::new();
-    opts.optflag("h", "help", "Print help message.");
-    opts.optopt("c", "config", "The location of config file.", "FILE");
-    opts.optflag("d", "dryrun", "Run without calling any process.");
-    opts.optflag("r", "skip-restore", "Skip restore backup.");
-    opts.optflag("b", "backup-only", "Backup only.");
-    opts.optflag("R", "run-only", "Run only. Do not backup or restore.");
-    let result = match opts.parse(&argv[1..]) {
-        Ok(m) => m,
+    let args = match cli::parse(&argv) {
+        Ok(Some(args)) => args,
+        Ok(None) => return ExitCode::from(0),
         Err(err) => {
-            println!("{}", err.to_string());
+            println!("{}", err);
             return ExitCode::from(1);
         }
     };
-    if result.opt_present("h") {
-        print_usage(&argv[0], &opts);
-        return ExitCode::from(0);
-    }
-    let cfg_path = result.opt_str("c").unwrap_or_else(|| {
+    let mut json_out = if args.json { Some(json_stdout()) } else { None };
+    let cfg_path = args.config.clone().unwrap_or_else(|| {
         let mut pb = utils::get_exe_path_else_current();
         pb.push("game-auto-sync.yml");
         pb.to_string_lossy().to_string()
     });
-    let cfg = match cfg::Config::from_file_path(&cfg_path) {
+    let mut cfg = match cfg::Config::from_file_path(&cfg_path) {
         Ok(cfg) => cfg,
         Err(e) => {
             println!("{}", e);
             return ExitCode::from(1);
         }
     };
-    if cfg.game_exe().unwrap_or(vec![]).is_empty() {
-        println!("game_exe need be set.");
+    if args.command == Command::Games {
+        let games = cfg.games();
+        if games.is_empty() {
+            println!("No game profiles in config file.");
+        }
+        for g in games {
+            println!("{}", g);
+        }
+        return ExitCode::from(0);
+    }
+    if let Err(e) = cfg.select_game(args.game.as_deref()) {
+        println!("{}", e);
         return ExitCode::from(1);
     }
-    let m = Main::new(
+    if let Some(level) = cfg.log_level() {
+        let path = cfg.log_file();
+        if let Err(e) = log::init(&path, level, cfg.log_max_size(), cfg.log_max_files()) {
+            println!("Failed to open log file {}: {}.", path.display(), e);
+        }
+    }
+    if cfg.capture_output()
+        && !args.dryrun
+        && matches!(args.command, Command::Run | Command::Backup | Command::Restore)
+    {
+        let name = format!("{}-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"), cfg.game());
+        let dir = cfg.capture_dir();
+        if let Err(e) = capture::init(&dir, &name, cfg.capture_tail_lines(), cfg.capture_max_files()) {
+            warning!("Failed to create output file in {}: {}.", dir.display(), e);
+        }
+    }
+    debug!(
+        "game-auto-sync {} started on {} for game {}: {:?}",
+        env!("CARGO_PKG_VERSION"),
+        utils::hostname(),
+        cfg.game(),
+        argv
+    );
+    match &args.command {
+        Command::Prune if cfg.backup_engine() != cfg::BackupEngine::Native => {
+            error!("Prune is only available when backup_engine is native.");
+            return ExitCode::from(1);
+        }
+        Command::HistoryList | Command::HistoryRestore(_) | Command::HistoryPrune if cfg.rclone_remote().is_none() => {
+            error!("History is only available when rclone_remote is set.");
+            return ExitCode::from(1);
+        }
+        Command::Diff if cfg.rclone_remote().is_none() => {
+            error!("Diff is only available when rclone_remote is set.");
+            return ExitCode::from(1);
+        }
+        Command::Run if cfg.game_exe().unwrap_or(vec![]).is_empty() => {
+            error!("game_exe need be set.");
+            return ExitCode::from(1);
+        }
+        _ => {}
+    }
+    let mut on_failure = HashMap::new();
+    let mut on_failure_all = None;
+    for s in &args.on_failure {
+        let (phase, policy) = match s.split_once('=') {
+            Some((p, policy)) if utils::FAILURE_PHASES.contains(&p) => (Some(p), policy),
+            _ => (None, s.as_str()),
+        };
+        let policy = match FailurePolicy::parse(policy) {
+            Some(p) => p,
+            None => {
+                println!("Invalid failure policy: {}", s);
+                return ExitCode::from(1);
+            }
+        };
+        match phase {
+            Some(p) => {
+                on_failure.insert(p.to_owned(), policy);
+            }
+            None => on_failure_all = Some(policy),
+        }
+    }
+    let mut m = Main::new(
         cfg,
-        result.opt_present("d"),
-        result.opt_present("r"),
-        result.opt_present("b"),
-        result.opt_present("R"),
+        args.dryrun,
+        args.skip_restore,
+        args.command == Command::Backup,
+        args.run_only,
+        args.command == Command::Restore,
     );
-    let e = match m.run() {
+    m._on_failure = on_failure;
+    m._on_failure_all = on_failure_all;
+    m._restore_from = args.restore_from;
+    if m._restore_from.is_some() && m.restore_from_target().is_none() {
+        error!("--restore-from needs backup_engine native, {{restore_from}} in restore_command or rclone_history.");
+        return ExitCode::from(1);
+    }
+    if args.command.has_phases() && (args.report.is_some() || args.json) {
+        report::init(m._cfg.game(), m._dryrun);
+    }
+    let re = match &args.command {
+        Command::Run | Command::Backup | Command::Restore => m.run(),
+        Command::List => m.list(json_out.as_deref_mut()),
+        Command::Status => m.status(),
+        Command::Diff => m.diff(),
+        Command::Doctor => m.doctor(),
+        Command::Prune => report::phase("prune", || m.prune()).map_err(Error::from),
+        Command::HistoryList => m.history_list(),
+        Command::HistoryRestore(name) => report::phase("history_restore", || m.history_restore(name)),
+        Command::HistoryPrune => report::phase("history_prune", || m.history_prune()),
+        Command::Games => Ok(()),
+    };
+    let error = re.as_ref().err().map(|e| e.to_string());
+    let e = match re {
         Ok(_) => 0,
         Err(e) => {
-            println!("{}", e);
+            error!("{}", e);
             1
         }
     };
+    if let Some(path) = args.report {
+        if let Some(r) = report::finish(error.clone(), true) {
+            if let Err(e) = std::fs::write(&path, r) {
+                warning!("Failed to write report {}: {}.", path, e);
+            }
+        }
+    }
+    if let Some(out) = &mut json_out {
+        if let Some(r) = report::finish(error, false) {
+            let _ = writeln!(out, "{}", r);
+        }
+    }
+    debug!("Exit with {}", e);
     if m._cfg.pause_at_exit() || (e == 1 && m._cfg.pause_on_backup_error()) {
         utils::enter_continue();
     }
-    return ExitCode::from(e);
+    ExitCode::from(e)
 }
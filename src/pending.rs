@@ -0,0 +1,58 @@
+use chrono::{DateTime, Local};
+use std::fs;
+use std::path::{Path, PathBuf};
+use yaml_rust::{yaml::Hash, Yaml, YamlEmitter, YamlLoader};
+
+/// Saved in the state directory when the saves of a game could not be uploaded.
+#[derive(Debug)]
+pub struct PendingUpload {
+    pub time: DateTime<Local>,
+    pub remote: String,
+    pub reason: String,
+}
+
+impl PendingUpload {
+    pub fn new(remote: String, reason: String) -> Self {
+        Self {
+            time: Local::now(),
+            remote,
+            reason,
+        }
+    }
+
+    /// The record file of `game` in `dir`.
+    pub fn path(dir: &Path, game: &str) -> PathBuf {
+        let name: String = game
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        dir.join(format!("pending-upload-{}.yml", name))
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        let s = fs::read_to_string(path).ok()?;
+        let doc = YamlLoader::load_from_str(&s).ok()?;
+        let doc = doc.first()?;
+        Some(Self {
+            time: DateTime::parse_from_rfc3339(doc["time"].as_str()?)
+                .ok()?
+                .with_timezone(&Local),
+            remote: doc["remote"].as_str()?.to_owned(),
+            reason: doc["reason"].as_str().unwrap_or_default().to_owned(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut obj = Hash::new();
+        obj.insert(Yaml::from_str("time"), Yaml::String(self.time.to_rfc3339()));
+        obj.insert(Yaml::from_str("remote"), Yaml::String(self.remote.clone()));
+        obj.insert(Yaml::from_str("reason"), Yaml::String(self.reason.clone()));
+        let mut s = String::new();
+        YamlEmitter::new(&mut s).dump(&Yaml::Hash(obj)).unwrap();
+        s.push('\n');
+        if let Some(p) = path.parent() {
+            fs::create_dir_all(p)?;
+        }
+        fs::write(path, s)
+    }
+}
@@ -0,0 +1,248 @@
+use crate::log::info;
+use regex::Regex;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How to find the process to wait for.
+#[derive(Debug)]
+pub enum Matcher {
+    /// File name of the executable, compared case-insensitively.
+    Name(String),
+    /// Full path of the executable.
+    Path(String),
+    /// Regex searched in the command line, arguments are joined with spaces.
+    Cmdline(Regex),
+}
+
+impl std::fmt::Display for Matcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Name(n) => write!(f, "{}", n),
+            Self::Path(p) => write!(f, "{}", p),
+            Self::Cmdline(r) => write!(f, "/{}/", r),
+        }
+    }
+}
+
+/// The last component of a path, with both `/` and `\` as separators,
+/// so names from Wine command lines work too.
+fn file_name(s: &str) -> &str {
+    s.rsplit(['/', '\\']).next().unwrap_or(s)
+}
+
+fn same_path(a: &str, b: &str) -> bool {
+    if cfg!(windows) {
+        a.replace('/', "\\").eq_ignore_ascii_case(&b.replace('/', "\\"))
+    } else {
+        Path::new(a) == Path::new(b)
+    }
+}
+
+/// Information of a running process.
+struct ProcInfo {
+    pid: u32,
+    exe: Option<String>,
+    cmdline: Vec<String>,
+}
+
+impl Matcher {
+    fn matches(&self, p: &ProcInfo) -> bool {
+        match self {
+            Self::Name(n) => {
+                p.exe.as_deref().is_some_and(|e| file_name(e).eq_ignore_ascii_case(n))
+                    || p.cmdline.first().is_some_and(|a| file_name(a).eq_ignore_ascii_case(n))
+            }
+            Self::Path(path) => {
+                p.exe.as_deref().is_some_and(|e| same_path(e, path))
+                    || p.cmdline.first().is_some_and(|a| same_path(a, path))
+            }
+            Self::Cmdline(r) => !p.cmdline.is_empty() && r.is_match(&p.cmdline.join(" ")),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::ProcInfo;
+    use std::fs;
+
+    pub fn processes() -> Vec<ProcInfo> {
+        let me = std::process::id();
+        let mut re = Vec::new();
+        let dir = match fs::read_dir("/proc") {
+            Ok(d) => d,
+            Err(_) => return re,
+        };
+        for e in dir.flatten() {
+            let pid = match e.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) {
+                Some(p) => p,
+                None => continue,
+            };
+            if pid == me {
+                continue;
+            }
+            let exe = fs::read_link(e.path().join("exe"))
+                .ok()
+                .map(|p| p.to_string_lossy().into_owned());
+            let cmdline = fs::read(e.path().join("cmdline"))
+                .map(|c| {
+                    c.split(|b| *b == 0)
+                        .filter(|a| !a.is_empty())
+                        .map(|a| String::from_utf8_lossy(a).into_owned())
+                        .collect()
+                })
+                .unwrap_or_default();
+            re.push(ProcInfo { pid, exe, cmdline });
+        }
+        re
+    }
+
+    pub fn is_alive(pid: u32) -> bool {
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            // A zombie has already exited.
+            Ok(stat) => match stat.rfind(')') {
+                Some(i) => !stat[i + 1..].trim_start().starts_with('Z'),
+                None => true,
+            },
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::ProcInfo;
+    use std::ffi::OsString;
+    use std::mem::size_of;
+    use std::os::windows::ffi::OsStringExt;
+    use winapi::shared::minwindef::{DWORD, FALSE};
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::processthreadsapi::{GetExitCodeProcess, OpenProcess};
+    use winapi::um::tlhelp32::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+    };
+    use winapi::um::minwinbase::STILL_ACTIVE;
+    use winapi::um::winbase::QueryFullProcessImageNameW;
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    fn from_wide(s: &[u16]) -> String {
+        let len = s.iter().position(|c| *c == 0).unwrap_or(s.len());
+        OsString::from_wide(&s[..len]).to_string_lossy().into_owned()
+    }
+
+    fn image_path(pid: u32) -> Option<String> {
+        let h = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) };
+        if h.is_null() {
+            return None;
+        }
+        let mut buf = [0u16; 32768];
+        let mut len = buf.len() as DWORD;
+        let ok = unsafe { QueryFullProcessImageNameW(h, 0, buf.as_mut_ptr(), &mut len) };
+        unsafe { CloseHandle(h) };
+        if ok == 0 {
+            return None;
+        }
+        Some(from_wide(&buf[..len as usize]))
+    }
+
+    /// The command line of other processes is not available without reading their memory,
+    /// so only the executable is reported.
+    pub fn processes() -> Vec<ProcInfo> {
+        let me = std::process::id();
+        let mut re = Vec::new();
+        let snap = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+        if snap == INVALID_HANDLE_VALUE {
+            return re;
+        }
+        let mut entry: PROCESSENTRY32W = unsafe { std::mem::zeroed() };
+        entry.dwSize = size_of::<PROCESSENTRY32W>() as DWORD;
+        let mut ok = unsafe { Process32FirstW(snap, &mut entry) };
+        while ok != 0 {
+            let pid = entry.th32ProcessID;
+            if pid != me && pid != 0 {
+                let name = from_wide(&entry.szExeFile);
+                re.push(ProcInfo {
+                    pid,
+                    exe: Some(image_path(pid).unwrap_or(name)),
+                    cmdline: Vec::new(),
+                });
+            }
+            ok = unsafe { Process32NextW(snap, &mut entry) };
+        }
+        unsafe { CloseHandle(snap) };
+        re
+    }
+
+    pub fn is_alive(pid: u32) -> bool {
+        let h = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) };
+        if h.is_null() {
+            return false;
+        }
+        let mut code: DWORD = 0;
+        let ok = unsafe { GetExitCodeProcess(h, &mut code) };
+        unsafe { CloseHandle(h) };
+        ok != 0 && code == STILL_ACTIVE
+    }
+}
+
+#[cfg(all(not(windows), not(target_os = "linux")))]
+mod imp {
+    use super::ProcInfo;
+
+    pub fn processes() -> Vec<ProcInfo> {
+        crate::log::warning!("Finding processes is not supported on this platform.");
+        Vec::new()
+    }
+
+    pub fn is_alive(_pid: u32) -> bool {
+        false
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The first running process matched by `m`.
+pub fn find(m: &Matcher) -> Option<u32> {
+    imp::processes().into_iter().find(|p| m.matches(p)).map(|p| p.pid)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WaitResult {
+    /// The process exited.
+    Exited,
+    /// The process did not appear in time.
+    NotFound,
+    /// The process was still running when the timeout was reached.
+    TimedOut,
+}
+
+/// Wait for a process matched by `m` to appear within `appear_timeout`, then wait for it to exit.
+///
+/// A game may restart itself, so other matching processes are waited for as well.
+/// `timeout` limits the total time spent waiting for the game.
+pub fn wait_for(m: &Matcher, appear_timeout: Duration, timeout: Option<Duration>) -> WaitResult {
+    let start = Instant::now();
+    let mut pid = loop {
+        if let Some(pid) = find(m) {
+            break pid;
+        }
+        if start.elapsed() >= appear_timeout {
+            return WaitResult::NotFound;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    info!("Waiting for process {} (pid {}) to exit.", m, pid);
+    let start = Instant::now();
+    loop {
+        while imp::is_alive(pid) {
+            if timeout.is_some_and(|t| start.elapsed() >= t) {
+                return WaitResult::TimedOut;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        match find(m) {
+            Some(p) => pid = p,
+            None => return WaitResult::Exited,
+        }
+    }
+}
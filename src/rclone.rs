@@ -0,0 +1,300 @@
+use crate::log::debug;
+use chrono::{DateTime, FixedOffset};
+use std::collections::HashMap;
+use subprocess::{CaptureData, Exec, ExitStatus, Redirection};
+
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum RcloneError {
+    Popen(subprocess::PopenError),
+    Json(serde_json::Error),
+    #[display(fmt = "rclone exited with {:?}", _0)]
+    Failed(ExitStatus),
+}
+
+/// A file listed by `rclone lsjson`.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Item {
+    pub path: String,
+    pub size: i64,
+    pub mod_time: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub hashes: HashMap<String, String>,
+}
+
+impl Item {
+    /// Compare contents by a hash type both sides have, or by size and time.
+    pub fn same_content(&self, other: &Item) -> bool {
+        for (k, v) in self.hashes.iter() {
+            if let Some(o) = other.hashes.get(k) {
+                if !v.is_empty() && !o.is_empty() {
+                    return v.eq_ignore_ascii_case(o);
+                }
+            }
+        }
+        self.size == other.size && (self.mod_time - other.mod_time).num_seconds().abs() < 1
+    }
+}
+
+/// Run `exec` and log its command line and exit status.
+fn capture(exec: Exec) -> Result<CaptureData, RcloneError> {
+    debug!("Call {}", exec.to_cmdline_lossy());
+    let re = exec.capture()?;
+    debug!("{:?}", re.exit_status);
+    Ok(re)
+}
+
+/// List all files under `path` recursively, with the filter flags `filter`. A missing directory is listed as empty.
+pub fn lsjson(exe: &str, path: &str, hash: bool, filter: &[String]) -> Result<Vec<Item>, RcloneError> {
+    let mut args = vec!["lsjson", "-R", "--files-only"];
+    if hash {
+        args.push("--hash");
+    }
+    args.extend(filter.iter().map(|s| s.as_str()));
+    args.push(path);
+    let re = capture(Exec::cmd(exe).args(&args).stdout(Redirection::Pipe))?;
+    match re.exit_status {
+        ExitStatus::Exited(0) => Ok(serde_json::from_slice(&re.stdout)?),
+        // Directory not found
+        ExitStatus::Exited(3) => Ok(Vec::new()),
+        e => Err(RcloneError::Failed(e)),
+    }
+}
+
+/// The first line of `rclone version`.
+pub fn version(exe: &str) -> Result<String, RcloneError> {
+    let re = capture(Exec::cmd(exe).arg("version").stdout(Redirection::Pipe))?;
+    match re.exit_status {
+        ExitStatus::Exited(0) => Ok(re.stdout_str().lines().next().unwrap_or_default().to_owned()),
+        e => Err(RcloneError::Failed(e)),
+    }
+}
+
+/// Read a remote file. Returns `None` if it does not exist.
+pub fn cat(exe: &str, path: &str) -> Result<Option<String>, RcloneError> {
+    let re = capture(
+        Exec::cmd(exe)
+            .args(&["cat", path])
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe),
+    )?;
+    match re.exit_status {
+        ExitStatus::Exited(0) => Ok(Some(re.stdout_str())),
+        // Directory or file not found
+        ExitStatus::Exited(3) | ExitStatus::Exited(4) => Ok(None),
+        e => Err(RcloneError::Failed(e)),
+    }
+}
+
+/// Write `data` to a remote file.
+pub fn rcat(exe: &str, path: &str, data: &str) -> Result<(), RcloneError> {
+    let re = capture(Exec::cmd(exe).args(&["rcat", path]).stdin(data))?;
+    match re.exit_status {
+        ExitStatus::Exited(0) => Ok(()),
+        e => Err(RcloneError::Failed(e)),
+    }
+}
+
+pub fn deletefile(exe: &str, path: &str) -> Result<(), RcloneError> {
+    let re = capture(Exec::cmd(exe).args(&["deletefile", path]))?;
+    match re.exit_status {
+        ExitStatus::Exited(0) => Ok(()),
+        e => Err(RcloneError::Failed(e)),
+    }
+}
+
+/// Remove a remote folder and all its contents.
+pub fn purge(exe: &str, path: &str) -> Result<(), RcloneError> {
+    let re = capture(Exec::cmd(exe).args(&["purge", path]))?;
+    match re.exit_status {
+        ExitStatus::Exited(0) => Ok(()),
+        e => Err(RcloneError::Failed(e)),
+    }
+}
+
+/// Files which are only on the local side, or differ and are newer locally.
+#[derive(Debug, Default)]
+pub struct Conflict {
+    pub local_only: Vec<String>,
+    pub local_newer: Vec<String>,
+    /// Files which differ and are newer on the remote side.
+    pub remote_newer: Vec<String>,
+}
+
+impl Conflict {
+    /// Whether both sides have changes the other one does not have.
+    pub fn diverged(&self) -> bool {
+        !self.is_empty() && !self.remote_newer.is_empty()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.local_only.is_empty() && self.local_newer.is_empty()
+    }
+}
+
+/// Find local files that would be lost by syncing `remote` over `local`.
+pub fn find_conflict(local: &[Item], remote: &[Item]) -> Conflict {
+    let remote: HashMap<_, _> = remote.iter().map(|i| (i.path.as_str(), i)).collect();
+    let mut re = Conflict::default();
+    for l in local {
+        match remote.get(l.path.as_str()) {
+            None => re.local_only.push(l.path.clone()),
+            Some(r) => {
+                if l.same_content(r) {
+                    continue;
+                }
+                // Different content at the same time can not be told apart, so it is asked about too.
+                if l.mod_time >= r.mod_time {
+                    re.local_newer.push(l.path.clone());
+                } else {
+                    re.remote_newer.push(l.path.clone());
+                }
+            }
+        }
+    }
+    re
+}
+
+/// Files of `dest` which syncing `source` to it would delete.
+pub fn sync_deletes<'a>(source: &[Item], dest: &'a [Item]) -> Vec<&'a str> {
+    let source: std::collections::HashSet<_> = source.iter().map(|i| i.path.as_str()).collect();
+    let mut re: Vec<_> = dest
+        .iter()
+        .map(|i| i.path.as_str())
+        .filter(|p| !source.contains(p))
+        .collect();
+    re.sort_unstable();
+    re
+}
+
+/// Why a sync is not safe, see [sync_risk].
+#[derive(Debug, PartialEq)]
+pub enum SyncRisk<'a> {
+    /// The files could not be listed.
+    Unchecked(String),
+    /// The source is empty, all `_0` files of the destination would be deleted.
+    Wipe(usize),
+    /// Files of the destination the sync would delete, more than allowed of its `total` files.
+    TooMany { deletes: Vec<&'a str>, total: usize },
+}
+
+/// Check a sync by the listings of its source and destination, it may delete at most `max_delete` percent
+/// of the destination. A sync which can not be listed is not safe. Returns `None` if it is safe.
+pub fn sync_risk(listing: &Result<(Vec<Item>, Vec<Item>), RcloneError>, max_delete: usize) -> Option<SyncRisk<'_>> {
+    let (source, dest) = match listing {
+        Ok(l) => l,
+        Err(e) => return Some(SyncRisk::Unchecked(e.to_string())),
+    };
+    let deletes = sync_deletes(source, dest);
+    if deletes.is_empty() {
+        None
+    } else if source.is_empty() {
+        Some(SyncRisk::Wipe(dest.len()))
+    } else if deletes.len() * 100 > dest.len() * max_delete {
+        Some(SyncRisk::TooMany {
+            deletes,
+            total: dest.len(),
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(path: &str, time: &str, hash: &str) -> Item {
+        let mut hashes = HashMap::new();
+        if !hash.is_empty() {
+            hashes.insert(String::from("md5"), hash.to_owned());
+        }
+        Item {
+            path: path.to_owned(),
+            size: 1,
+            mod_time: DateTime::parse_from_rfc3339(time).unwrap(),
+            hashes,
+        }
+    }
+
+    const OLD: &str = "2026-10-01T10:00:00+00:00";
+    const NEW: &str = "2026-10-01T20:00:00+00:00";
+
+    #[test]
+    fn conflicts() {
+        // (local, remote, local_only, local_newer, remote_newer)
+        let cases = [
+            (vec![item("a", NEW, "2")], vec![item("a", OLD, "1")], 0, 1, 0),
+            (vec![item("a", OLD, "1")], vec![item("a", NEW, "2")], 0, 0, 1),
+            (vec![item("a", OLD, "1")], vec![], 1, 0, 0),
+            (vec![], vec![item("a", OLD, "1")], 0, 0, 0),
+            (vec![item("a", OLD, "1")], vec![item("a", OLD, "1")], 0, 0, 0),
+            // The same hash is the same file, whatever the time.
+            (vec![item("a", NEW, "1")], vec![item("a", OLD, "1")], 0, 0, 0),
+            (vec![item("a", OLD, "1")], vec![item("a", OLD, "2")], 0, 1, 0),
+            // Without hashes, size and time are compared.
+            (vec![item("a", OLD, "")], vec![item("a", OLD, "")], 0, 0, 0),
+            (vec![item("a", NEW, "")], vec![item("a", OLD, "")], 0, 1, 0),
+        ];
+        for (i, (l, r, only, newer, remote)) in cases.iter().enumerate() {
+            let c = find_conflict(l, r);
+            let got = (c.local_only.len(), c.local_newer.len(), c.remote_newer.len());
+            assert_eq!(got, (*only, *newer, *remote), "case {}", i);
+        }
+    }
+
+    #[test]
+    fn diverged() {
+        let l = [item("a", NEW, "2"), item("b", OLD, "1")];
+        let r = [item("a", OLD, "1"), item("b", NEW, "2")];
+        let c = find_conflict(&l, &r);
+        assert!(c.diverged());
+        assert!(!find_conflict(&r[..1], &l[..1]).diverged());
+        assert!(!find_conflict(&[], &r).diverged());
+    }
+
+    #[test]
+    fn deletes() {
+        let a = [item("a", OLD, "1"), item("c", OLD, "1"), item("b", OLD, "1")];
+        let b = [item("a", NEW, "2")];
+        assert_eq!(sync_deletes(&b, &a), ["b", "c"]);
+        assert!(sync_deletes(&a, &b).is_empty());
+        assert!(sync_deletes(&a, &a).is_empty());
+        assert_eq!(sync_deletes(&[], &b), ["a"]);
+    }
+
+    fn listing(source: &[&str], dest: &[&str]) -> Result<(Vec<Item>, Vec<Item>), RcloneError> {
+        let items = |p: &[&str]| p.iter().map(|p| item(p, OLD, "1")).collect();
+        Ok((items(source), items(dest)))
+    }
+
+    #[test]
+    fn sync_unchecked() {
+        let failed = Err(RcloneError::Failed(ExitStatus::Exited(1)));
+        assert!(matches!(sync_risk(&failed, 50), Some(SyncRisk::Unchecked(_))));
+    }
+
+    #[test]
+    fn sync_empty_source() {
+        assert_eq!(sync_risk(&listing(&[], &["a", "b"]), 100), Some(SyncRisk::Wipe(2)));
+        // Nothing to delete is safe, even from an empty source.
+        assert_eq!(sync_risk(&listing(&[], &[]), 0), None);
+        assert_eq!(sync_risk(&listing(&["a"], &[]), 0), None);
+    }
+
+    #[test]
+    fn sync_threshold() {
+        let dest = ["a", "b", "c", "d"];
+        // Two of four is exactly 50%, which is allowed.
+        assert_eq!(sync_risk(&listing(&["a", "b"], &dest), 50), None);
+        assert_eq!(
+            sync_risk(&listing(&["a"], &dest), 50),
+            Some(SyncRisk::TooMany {
+                deletes: vec!["b", "c", "d"],
+                total: 4
+            })
+        );
+        assert!(sync_risk(&listing(&["a", "b", "c"], &dest), 0).is_some());
+        assert_eq!(sync_risk(&listing(&["a"], &dest), 100), None);
+    }
+}
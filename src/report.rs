@@ -0,0 +1,141 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::sync::Mutex;
+use subprocess::ExitStatus;
+
+#[derive(Serialize)]
+pub struct Phase {
+    pub name: &'static str,
+    pub command: Option<Vec<String>>,
+    pub start: DateTime<Local>,
+    pub end: Option<DateTime<Local>>,
+    pub exit_code: Option<u32>,
+    /// Set if the command was killed by a signal.
+    pub signal: Option<u8>,
+    /// Whether the user chose to continue after a failure.
+    pub user_continued: bool,
+    /// `success`, `failure` or `dryrun`.
+    pub outcome: &'static str,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub version: &'static str,
+    pub game: String,
+    pub host: String,
+    pub dryrun: bool,
+    pub start: DateTime<Local>,
+    pub end: Option<DateTime<Local>>,
+    pub phases: Vec<Phase>,
+    /// `success` or `failure`.
+    pub outcome: &'static str,
+    pub error: Option<String>,
+}
+
+struct State {
+    report: Option<Report>,
+    /// The running phase.
+    current: Option<Phase>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    report: None,
+    current: None,
+});
+
+/// Start recording a report.
+pub fn init(game: &str, dryrun: bool) {
+    STATE.lock().unwrap().report = Some(Report {
+        version: env!("CARGO_PKG_VERSION"),
+        game: game.to_owned(),
+        host: crate::utils::hostname(),
+        dryrun,
+        start: Local::now(),
+        end: None,
+        phases: Vec::new(),
+        outcome: "success",
+        error: None,
+    });
+}
+
+/// Record `f` as the phase `name`.
+pub fn phase<T, E: std::fmt::Display, F: FnOnce() -> Result<T, E>>(name: &'static str, f: F) -> Result<T, E> {
+    {
+        let mut state = STATE.lock().unwrap();
+        if state.report.is_none() {
+            drop(state);
+            return f();
+        }
+        state.current = Some(Phase {
+            name,
+            command: None,
+            start: Local::now(),
+            end: None,
+            exit_code: None,
+            signal: None,
+            user_continued: false,
+            outcome: "success",
+            error: None,
+        });
+    }
+    let re = f();
+    let mut state = STATE.lock().unwrap();
+    if let Some(mut p) = state.current.take() {
+        p.end = Some(Local::now());
+        let dryrun = state.report.as_ref().is_some_and(|r| r.dryrun);
+        if let Err(e) = &re {
+            p.outcome = "failure";
+            p.error = Some(e.to_string());
+        } else if p.user_continued || p.exit_code.is_some_and(|c| c != 0) || p.signal.is_some() {
+            p.outcome = "failure";
+        } else if dryrun {
+            p.outcome = "dryrun";
+        }
+        if let Some(r) = state.report.as_mut() {
+            r.phases.push(p);
+        }
+    }
+    re
+}
+
+fn with_current<F: FnOnce(&mut Phase)>(f: F) {
+    if let Some(p) = STATE.lock().unwrap().current.as_mut() {
+        f(p);
+    }
+}
+
+/// Record the command line of the running phase.
+pub fn command(cml: &[String]) {
+    with_current(|p| p.command = Some(cml.to_vec()));
+}
+
+/// Record the exit status of the command of the running phase.
+pub fn status(e: &ExitStatus) {
+    with_current(|p| match e {
+        ExitStatus::Exited(c) => p.exit_code = Some(*c),
+        ExitStatus::Signaled(s) => p.signal = Some(*s),
+        _ => {}
+    });
+}
+
+/// Record that the user chose to continue after a failure.
+pub fn user_continued() {
+    with_current(|p| p.user_continued = true);
+}
+
+/// Finish the report with the result of the run, returns it as JSON.
+pub fn finish(error: Option<String>, pretty: bool) -> Option<String> {
+    let mut state = STATE.lock().unwrap();
+    let r = state.report.as_mut()?;
+    r.end = Some(Local::now());
+    if error.is_some() {
+        r.outcome = "failure";
+    }
+    r.error = error;
+    if pretty {
+        serde_json::to_string_pretty(r).ok()
+    } else {
+        serde_json::to_string(r).ok()
+    }
+}
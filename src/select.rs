@@ -0,0 +1,150 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+/// Which backup to restore, from `--restore-from`.
+#[derive(Clone, Debug)]
+pub enum Selection {
+    /// The newest backup.
+    Latest,
+    /// A snapshot id or history name.
+    Id(String),
+    /// The newest backup made at or before this time.
+    AsOf(DateTime<Local>),
+    /// The backup N sessions before the newest one.
+    Ago(usize),
+}
+
+const TIME_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"];
+
+fn parse_time(s: &str) -> Option<DateTime<Local>> {
+    let t = TIME_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        // A date alone means the end of that day.
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?.and_hms_opt(23, 59, 59))?;
+    Local.from_local_datetime(&t).earliest()
+}
+
+impl Selection {
+    /// Parse `latest`, `~N`, `N sessions ago`, a time like `2026-10-01 20:00` or a date,
+    /// optionally prefixed with `as of`. Anything else is taken as an id.
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim();
+        if s.is_empty() || s == "latest" {
+            return Self::Latest;
+        }
+        if let Some(n) = s.strip_prefix('~').and_then(|n| n.parse().ok()) {
+            return Self::Ago(n);
+        }
+        let ago = s
+            .strip_suffix(" sessions ago")
+            .or_else(|| s.strip_suffix(" session ago"))
+            .and_then(|n| n.trim().parse().ok());
+        if let Some(n) = ago {
+            return Self::Ago(n);
+        }
+        if let Some(t) = parse_time(s.strip_prefix("as of ").unwrap_or(s).trim()) {
+            return Self::AsOf(t);
+        }
+        Self::Id(s.to_owned())
+    }
+
+    /// Pick from `items`, sorted oldest first, using `id` and `time` to read them.
+    pub fn pick<T, I, F>(&self, items: &[T], id: I, time: F) -> Option<usize>
+    where
+        I: Fn(&T) -> &str,
+        F: Fn(&T) -> DateTime<Local>,
+    {
+        match self {
+            Self::Latest => items.len().checked_sub(1),
+            Self::Id(s) => items.iter().position(|i| id(i) == s),
+            Self::AsOf(t) => items.iter().rposition(|i| time(i) <= *t),
+            Self::Ago(n) => items.len().checked_sub(n + 1),
+        }
+    }
+}
+
+impl std::fmt::Display for Selection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Latest => write!(f, "latest"),
+            Self::Id(s) => write!(f, "{}", s),
+            Self::AsOf(t) => write!(f, "as of {}", t.format("%Y-%m-%d %H:%M:%S")),
+            Self::Ago(1) => write!(f, "1 session ago"),
+            Self::Ago(n) => write!(f, "{} sessions ago", n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Local> {
+        parse_time(s).unwrap()
+    }
+
+    /// Native snapshots, oldest first, as (id, time).
+    fn snapshots() -> Vec<(String, DateTime<Local>)> {
+        ["2026-10-01 10:00", "2026-10-01 20:00", "2026-10-02 08:00"]
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (format!("s{}", i), at(t)))
+            .collect()
+    }
+
+    fn pick(sel: &str) -> Option<usize> {
+        Selection::parse(sel).pick(&snapshots(), |s| s.0.as_str(), |s| s.1)
+    }
+
+    #[test]
+    fn parse() {
+        assert!(matches!(Selection::parse(""), Selection::Latest));
+        assert!(matches!(Selection::parse(" latest "), Selection::Latest));
+        assert!(matches!(Selection::parse("~2"), Selection::Ago(2)));
+        assert!(matches!(Selection::parse("3 sessions ago"), Selection::Ago(3)));
+        assert!(matches!(Selection::parse("1 session ago"), Selection::Ago(1)));
+        assert!(matches!(Selection::parse("0 sessions ago"), Selection::Ago(0)));
+        assert!(matches!(Selection::parse("2026-10-01 20:00"), Selection::AsOf(t) if t == at("2026-10-01 20:00:00")));
+        assert!(matches!(Selection::parse("as of 2026-10-01T20:00:30"), Selection::AsOf(t) if t == at("2026-10-01 20:00:30")));
+        // A date alone is the end of the day.
+        assert!(matches!(Selection::parse("2026-10-01"), Selection::AsOf(t) if t == at("2026-10-01 23:59:59")));
+        assert!(matches!(Selection::parse("20261001-200000"), Selection::Id(s) if s == "20261001-200000"));
+        assert!(matches!(Selection::parse("~x"), Selection::Id(s) if s == "~x"));
+        assert!(matches!(Selection::parse("many sessions ago"), Selection::Id(_)));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in ["latest", "1 session ago", "3 sessions ago", "as of 2026-10-01 20:00:00", "abc"] {
+            assert_eq!(Selection::parse(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn pick_latest_and_id() {
+        assert_eq!(pick("latest"), Some(2));
+        assert_eq!(pick("s1"), Some(1));
+        assert_eq!(pick("s9"), None);
+        let empty: Vec<(String, DateTime<Local>)> = Vec::new();
+        assert_eq!(Selection::Latest.pick(&empty, |s| s.0.as_str(), |s| s.1), None);
+    }
+
+    #[test]
+    fn pick_sessions_ago() {
+        assert_eq!(pick("0 sessions ago"), Some(2));
+        assert_eq!(pick("1 session ago"), Some(1));
+        assert_eq!(pick("~2"), Some(0));
+        assert_eq!(pick("3 sessions ago"), None);
+    }
+
+    #[test]
+    fn pick_as_of() {
+        // Exactly at a snapshot selects it.
+        assert_eq!(pick("2026-10-01 20:00"), Some(1));
+        assert_eq!(pick("2026-10-01 19:59"), Some(0));
+        assert_eq!(pick("2026-10-01"), Some(1));
+        assert_eq!(pick("2026-10-03"), Some(2));
+        // Before the oldest one.
+        assert_eq!(pick("2026-10-01 09:59"), None);
+    }
+}
@@ -0,0 +1,447 @@
+use super::{load_yaml, new_id, replace, save_yaml, Entry, EntryKind, Snapshot, SnapshotError, Store};
+use crate::log::warning;
+use chrono::{DateTime, Local};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use yaml_rust::{yaml::Hash, Yaml};
+
+const OBJECTS: &str = "objects";
+const SNAPSHOTS: &str = "snapshots";
+
+/// File contents are stored once under `objects/` by their SHA-256 hash,
+/// and every snapshot is a small manifest under `snapshots/`.
+pub struct DedupStore {
+    dir: PathBuf,
+}
+
+struct FileRecord {
+    /// Path inside the snapshot, `/` separated. The first component is the entry index.
+    path: String,
+    hash: String,
+    size: u64,
+    mtime: DateTime<Local>,
+}
+
+struct Manifest {
+    snapshot: Snapshot,
+    /// Directories inside the snapshot, so empty ones are restored too.
+    dirs: Vec<String>,
+    files: Vec<FileRecord>,
+}
+
+impl Manifest {
+    fn from_yaml(id: String, doc: &Yaml) -> Result<Self, SnapshotError> {
+        let invalid = || SnapshotError::InvalidManifest(id.clone());
+        let mut dirs = Vec::new();
+        for d in doc["dirs"].as_vec().ok_or_else(invalid)? {
+            dirs.push(d.as_str().ok_or_else(invalid)?.to_owned());
+        }
+        let mut files = Vec::new();
+        for f in doc["files"].as_vec().ok_or_else(invalid)? {
+            files.push(FileRecord {
+                path: f["path"].as_str().ok_or_else(invalid)?.to_owned(),
+                hash: f["hash"].as_str().ok_or_else(invalid)?.to_owned(),
+                size: f["size"].as_i64().ok_or_else(invalid)? as u64,
+                mtime: f["mtime"]
+                    .as_str()
+                    .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                    .ok_or_else(invalid)?
+                    .with_timezone(&Local),
+            });
+        }
+        Ok(Self {
+            snapshot: Snapshot::from_yaml(id, doc)?,
+            dirs,
+            files,
+        })
+    }
+
+    fn to_hash(&self) -> Hash {
+        let mut obj = self.snapshot.to_hash();
+        let dirs = self.dirs.iter().map(|d| Yaml::String(d.clone())).collect();
+        obj.insert(Yaml::from_str("dirs"), Yaml::Array(dirs));
+        let files = self
+            .files
+            .iter()
+            .map(|f| {
+                let mut h = Hash::new();
+                h.insert(Yaml::from_str("path"), Yaml::String(f.path.clone()));
+                h.insert(Yaml::from_str("hash"), Yaml::String(f.hash.clone()));
+                h.insert(Yaml::from_str("size"), Yaml::Integer(f.size as i64));
+                h.insert(Yaml::from_str("mtime"), Yaml::String(f.mtime.to_rfc3339()));
+                Yaml::Hash(h)
+            })
+            .collect();
+        obj.insert(Yaml::from_str("files"), Yaml::Array(files));
+        obj
+    }
+}
+
+/// Copy `src` into `dest` and return the hash of the copied content.
+fn copy_hashed(src: &Path, dest: &mut File) -> std::io::Result<String> {
+    let mut f = File::open(src)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 65536];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        dest.write_all(&buf[..n])?;
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Join a `/` separated path inside a snapshot onto `base`.
+fn join_rel(base: &Path, rel: &str) -> PathBuf {
+    rel.split('/').fold(base.to_path_buf(), |p, c| p.join(c))
+}
+
+impl DedupStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(OBJECTS).join(&hash[..2]).join(hash)
+    }
+
+    fn manifest_path(&self, id: &str) -> PathBuf {
+        self.dir.join(SNAPSHOTS).join(format!("{}.yml", id))
+    }
+
+    /// Store the content of `src` and return its hash.
+    fn store_file(&self, src: &Path) -> std::io::Result<String> {
+        let tmp = self.dir.join(OBJECTS).join(format!(".{}.tmp", std::process::id()));
+        let mut f = File::create(&tmp)?;
+        // The file may change while the game is still running, so hash what is actually copied.
+        let hash = copy_hashed(src, &mut f)?;
+        drop(f);
+        let dest = self.object_path(&hash);
+        if dest.exists() {
+            fs::remove_file(&tmp)?;
+        } else {
+            fs::create_dir_all(dest.parent().unwrap())?;
+            fs::rename(&tmp, &dest)?;
+        }
+        Ok(hash)
+    }
+
+    fn add_file(&self, src: &Path, rel: String, files: &mut Vec<FileRecord>) -> std::io::Result<()> {
+        let meta = fs::metadata(src)?;
+        files.push(FileRecord {
+            hash: self.store_file(src)?,
+            path: rel,
+            size: meta.len(),
+            mtime: DateTime::from(meta.modified()?),
+        });
+        Ok(())
+    }
+
+    fn add_dir(
+        &self,
+        src: &Path,
+        rel: &str,
+        dirs: &mut Vec<String>,
+        files: &mut Vec<FileRecord>,
+    ) -> std::io::Result<()> {
+        for e in fs::read_dir(src)? {
+            let e = e?;
+            let rel = format!("{}/{}", rel, e.file_name().to_string_lossy());
+            if e.file_type()?.is_dir() {
+                dirs.push(rel.clone());
+                self.add_dir(&e.path(), &rel, dirs, files)?;
+            } else {
+                self.add_file(&e.path(), rel, files)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete objects which are not used by any snapshot.
+    fn gc(&self) -> Result<(), SnapshotError> {
+        let mut used = HashSet::new();
+        for s in self.list()? {
+            let doc = load_yaml(&self.manifest_path(&s.id))?;
+            for f in Manifest::from_yaml(s.id, &doc)?.files {
+                used.insert(f.hash);
+            }
+        }
+        let objects = self.dir.join(OBJECTS);
+        if !objects.is_dir() {
+            return Ok(());
+        }
+        for d in fs::read_dir(&objects)? {
+            let d = d?;
+            if !d.file_type()?.is_dir() {
+                continue;
+            }
+            for f in fs::read_dir(d.path())? {
+                let f = f?;
+                if !used.contains(f.file_name().to_string_lossy().as_ref()) {
+                    fs::remove_file(f.path())?;
+                }
+            }
+            if fs::read_dir(d.path())?.next().is_none() {
+                fs::remove_dir(d.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_file(&self, f: &FileRecord, dest: &Path) -> std::io::Result<()> {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(self.object_path(&f.hash), dest)?;
+        File::options()
+            .write(true)
+            .open(dest)?
+            .set_modified(f.mtime.into())
+    }
+}
+
+impl Store for DedupStore {
+    fn backup(&self, paths: &[String], tags: &[String]) -> Result<Snapshot, SnapshotError> {
+        fs::create_dir_all(self.dir.join(OBJECTS))?;
+        fs::create_dir_all(self.dir.join(SNAPSHOTS))?;
+        let time = Local::now();
+        let id = new_id(&time, |id| self.manifest_path(id).exists());
+        let mut entries = Vec::new();
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let src = Path::new(path);
+            let kind = if src.is_dir() {
+                self.add_dir(src, &i.to_string(), &mut dirs, &mut files)?;
+                EntryKind::Dir
+            } else if src.exists() {
+                self.add_file(src, i.to_string(), &mut files)?;
+                EntryKind::File
+            } else {
+                warning!("{} not found, skipped.", path);
+                EntryKind::Missing
+            };
+            entries.push(Entry {
+                path: path.to_owned(),
+                kind,
+            });
+        }
+        let mut snapshot = Snapshot::new(id, time, entries, tags);
+        snapshot.files = files.len();
+        snapshot.size = files.iter().map(|f| f.size).sum();
+        let manifest = Manifest { snapshot, dirs, files };
+        let tmp = self.dir.join(SNAPSHOTS).join(format!(".{}.tmp", manifest.snapshot.id));
+        save_yaml(&tmp, manifest.to_hash())?;
+        fs::rename(&tmp, self.manifest_path(&manifest.snapshot.id))?;
+        Ok(manifest.snapshot)
+    }
+
+    fn list(&self) -> Result<Vec<Snapshot>, SnapshotError> {
+        let mut re = Vec::new();
+        let dir = self.dir.join(SNAPSHOTS);
+        if !dir.is_dir() {
+            return Ok(re);
+        }
+        for e in fs::read_dir(&dir)? {
+            let name = e?.file_name().to_string_lossy().to_string();
+            let id = match name.strip_suffix(".yml") {
+                Some(id) if !id.starts_with('.') => id.to_owned(),
+                _ => continue,
+            };
+            let doc = load_yaml(&self.manifest_path(&id))?;
+            let m = Manifest::from_yaml(id, &doc)?;
+            let mut s = m.snapshot;
+            s.files = m.files.len();
+            s.size = m.files.iter().map(|f| f.size).sum();
+            re.push(s);
+        }
+        re.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.id.cmp(&b.id)));
+        Ok(re)
+    }
+
+    fn restore(&self, snapshot: &Snapshot, paths: &[String]) -> Result<(), SnapshotError> {
+        let doc = load_yaml(&self.manifest_path(&snapshot.id))?;
+        let manifest = Manifest::from_yaml(snapshot.id.clone(), &doc)?;
+        // Check every object first, a broken snapshot must not replace any saves.
+        for p in paths {
+            let i = match manifest.snapshot.find_entry(p) {
+                Some((i, _)) => i,
+                None => continue,
+            };
+            let (key, prefix) = (i.to_string(), format!("{}/", i));
+            for f in manifest.files.iter() {
+                if (f.path == key || f.path.starts_with(&prefix)) && !self.object_path(&f.hash).is_file() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("Object {} of {} is missing in snapshot {}.", f.hash, p, snapshot.id),
+                    )
+                    .into());
+                }
+            }
+        }
+        for p in paths {
+            let (i, entry) = match manifest.snapshot.find_entry(p) {
+                Some(e) => e,
+                None => continue,
+            };
+            let dest = Path::new(p);
+            let prefix = format!("{}/", i);
+            match entry.kind {
+                EntryKind::Missing => continue,
+                EntryKind::Dir => replace(dest, |tmp| {
+                    fs::create_dir_all(tmp)?;
+                    for d in manifest.dirs.iter() {
+                        if let Some(rel) = d.strip_prefix(&prefix) {
+                            fs::create_dir_all(join_rel(tmp, rel))?;
+                        }
+                    }
+                    for f in manifest.files.iter() {
+                        if let Some(rel) = f.path.strip_prefix(&prefix) {
+                            self.restore_file(f, &join_rel(tmp, rel))?;
+                        }
+                    }
+                    Ok(())
+                })?,
+                EntryKind::File => {
+                    let key = i.to_string();
+                    if let Some(f) = manifest.files.iter().find(|f| f.path == key) {
+                        replace(dest, |tmp| self.restore_file(f, tmp))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, snapshots: &[&Snapshot]) -> Result<(), SnapshotError> {
+        for s in snapshots {
+            fs::remove_file(self.manifest_path(&s.id))?;
+        }
+        self.gc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    /// A save folder and a single save file under `dir`.
+    fn saves(dir: &Path) -> Vec<String> {
+        let saves = dir.join("saves");
+        fs::create_dir_all(saves.join("sub")).unwrap();
+        fs::create_dir_all(saves.join("empty")).unwrap();
+        fs::write(saves.join("a.sav"), "a1").unwrap();
+        fs::write(saves.join("sub").join("b.sav"), "b1").unwrap();
+        fs::write(dir.join("config.ini"), "c1").unwrap();
+        vec![saves.to_string_lossy().to_string(), dir.join("config.ini").to_string_lossy().to_string()]
+    }
+
+    /// Hashes of all stored objects.
+    fn objects(store: &DedupStore) -> HashSet<String> {
+        let mut re = HashSet::new();
+        for d in fs::read_dir(store.dir.join(OBJECTS)).unwrap() {
+            let d = d.unwrap();
+            assert!(d.file_type().unwrap().is_dir());
+            for f in fs::read_dir(d.path()).unwrap() {
+                re.insert(f.unwrap().file_name().to_string_lossy().to_string());
+            }
+        }
+        re
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = test_dir("dedup-round-trip");
+        let paths = saves(&dir);
+        let store = DedupStore::new(dir.join("snapshots"));
+        let s = store.backup(&paths, &["pc".to_owned()]).unwrap();
+        assert_eq!((s.files, s.size), (3, 6));
+        assert_eq!(objects(&store).len(), 3);
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, s.id);
+        assert_eq!((listed[0].files, listed[0].size), (3, 6));
+        assert_eq!(listed[0].tags, ["pc"]);
+
+        let saves = dir.join("saves");
+        let mtime = fs::metadata(saves.join("a.sav")).unwrap().modified().unwrap();
+        fs::write(saves.join("a.sav"), "a2").unwrap();
+        fs::write(saves.join("new.sav"), "n").unwrap();
+        fs::remove_dir_all(saves.join("sub")).unwrap();
+        fs::write(dir.join("config.ini"), "c2").unwrap();
+        store.restore(&listed[0], &paths).unwrap();
+        assert_eq!(fs::read_to_string(saves.join("a.sav")).unwrap(), "a1");
+        assert_eq!(fs::metadata(saves.join("a.sav")).unwrap().modified().unwrap(), mtime);
+        assert_eq!(fs::read_to_string(saves.join("sub").join("b.sav")).unwrap(), "b1");
+        assert!(saves.join("empty").is_dir());
+        assert!(!saves.join("new.sav").exists());
+        assert_eq!(fs::read_to_string(dir.join("config.ini")).unwrap(), "c1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restore_missing_entry() {
+        let dir = test_dir("dedup-missing");
+        let paths = vec![dir.join("later.sav").to_string_lossy().to_string()];
+        let store = DedupStore::new(dir.join("snapshots"));
+        let s = store.backup(&paths, &[]).unwrap();
+        assert_eq!(s.entries[0].kind, EntryKind::Missing);
+        // A path which did not exist is left as it is.
+        fs::write(dir.join("later.sav"), "l").unwrap();
+        store.restore(&s, &paths).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("later.sav")).unwrap(), "l");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remove_keeps_shared_objects() {
+        let dir = test_dir("dedup-gc");
+        let paths = saves(&dir);
+        let store = DedupStore::new(dir.join("snapshots"));
+        let first = store.backup(&paths, &[]).unwrap();
+        let before = objects(&store);
+        fs::write(dir.join("saves").join("a.sav"), "a2").unwrap();
+        let second = store.backup(&paths, &[]).unwrap();
+        // Only the changed file is stored again.
+        assert_eq!(objects(&store).len(), 4);
+
+        store.remove(&[&first]).unwrap();
+        let after = objects(&store);
+        assert_eq!(after.len(), 3);
+        assert_eq!(before.difference(&after).count(), 1);
+        store.restore(&second, &paths).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("saves").join("a.sav")).unwrap(), "a2");
+        assert_eq!(fs::read_to_string(dir.join("saves").join("sub").join("b.sav")).unwrap(), "b1");
+
+        // Empty prefix folders go with the last of their objects.
+        store.remove(&[&second]).unwrap();
+        assert_eq!(fs::read_dir(dir.join("snapshots").join(OBJECTS)).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn list_without_stats() {
+        let dir = test_dir("dedup-no-stats");
+        let paths = saves(&dir);
+        let store = DedupStore::new(dir.join("snapshots"));
+        let s = store.backup(&paths, &[]).unwrap();
+        // Manifests of older versions have no file count, it is taken from the file list.
+        let manifest = store.manifest_path(&s.id);
+        let doc = fs::read_to_string(&manifest).unwrap();
+        let doc: Vec<_> = doc.lines().filter(|l| !l.starts_with("file_count")).collect();
+        fs::write(&manifest, doc.join("\n")).unwrap();
+        let listed = store.list().unwrap();
+        assert_eq!((listed[0].files, listed[0].size), (3, 6));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+mod dedup;
+mod plain;
+pub mod retention;
+
+use crate::log::warning;
+use chrono::{DateTime, Local};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use yaml_rust::{yaml::Hash, EmitError, ScanError, Yaml, YamlEmitter, YamlLoader};
+
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum SnapshotError {
+    IO(std::io::Error),
+    Yaml(ScanError),
+    Emit(EmitError),
+    #[display(fmt = "Invalid snapshot manifest: {}", _0)]
+    #[from(ignore)]
+    InvalidManifest(String),
+    #[display(fmt = "No snapshot matches {}", _0)]
+    #[from(ignore)]
+    NotFound(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    Dir,
+    File,
+    Missing,
+}
+
+impl EntryKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Dir => "dir",
+            EntryKind::File => "file",
+            EntryKind::Missing => "missing",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "dir" => Some(EntryKind::Dir),
+            "file" => Some(EntryKind::File),
+            "missing" => Some(EntryKind::Missing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    /// The path as written in the config file.
+    pub path: String,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug)]
+pub struct Snapshot {
+    pub id: String,
+    pub time: DateTime<Local>,
+    pub host: String,
+    pub entries: Vec<Entry>,
+    /// Labels from `snapshot_tags` when the snapshot was made.
+    pub tags: Vec<String>,
+    /// Number and total size of the files, stored in the manifest.
+    pub files: usize,
+    pub size: u64,
+}
+
+impl Snapshot {
+    fn new(id: String, time: DateTime<Local>, entries: Vec<Entry>, tags: &[String]) -> Self {
+        Self {
+            id,
+            time,
+            host: crate::utils::hostname(),
+            entries,
+            tags: tags.to_vec(),
+            files: 0,
+            size: 0,
+        }
+    }
+
+    fn from_yaml(id: String, doc: &Yaml) -> Result<Self, SnapshotError> {
+        let invalid = || SnapshotError::InvalidManifest(id.clone());
+        let time = doc["time"]
+            .as_str()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .ok_or_else(invalid)?
+            .with_timezone(&Local);
+        let host = doc["host"].as_str().unwrap_or_default().to_owned();
+        let mut entries = Vec::new();
+        for e in doc["paths"].as_vec().ok_or_else(invalid)? {
+            let path = e["path"].as_str().ok_or_else(invalid)?.to_owned();
+            let kind = e["kind"]
+                .as_str()
+                .and_then(EntryKind::from_str)
+                .ok_or_else(invalid)?;
+            entries.push(Entry { path, kind });
+        }
+        let tags = doc["tags"]
+            .as_vec()
+            .map(|v| v.iter().filter_map(|t| t.as_str()).map(|t| t.to_owned()).collect())
+            .unwrap_or_default();
+        Ok(Self {
+            id,
+            time,
+            host,
+            entries,
+            tags,
+            files: doc["file_count"].as_i64().unwrap_or(0).max(0) as usize,
+            size: doc["size"].as_i64().unwrap_or(0).max(0) as u64,
+        })
+    }
+
+    fn to_hash(&self) -> Hash {
+        let mut obj = Hash::new();
+        obj.insert(Yaml::from_str("time"), Yaml::String(self.time.to_rfc3339()));
+        obj.insert(Yaml::from_str("host"), Yaml::String(self.host.clone()));
+        let paths = self
+            .entries
+            .iter()
+            .map(|e| {
+                let mut h = Hash::new();
+                h.insert(Yaml::from_str("path"), Yaml::String(e.path.clone()));
+                h.insert(Yaml::from_str("kind"), Yaml::from_str(e.kind.as_str()));
+                Yaml::Hash(h)
+            })
+            .collect();
+        obj.insert(Yaml::from_str("paths"), Yaml::Array(paths));
+        if !self.tags.is_empty() {
+            let tags = self.tags.iter().map(|t| Yaml::String(t.clone())).collect();
+            obj.insert(Yaml::from_str("tags"), Yaml::Array(tags));
+        }
+        obj.insert(Yaml::from_str("file_count"), Yaml::Integer(self.files as i64));
+        obj.insert(Yaml::from_str("size"), Yaml::Integer(self.size as i64));
+        obj
+    }
+
+    /// Find the entry for a configured path and its index in the snapshot.
+    fn find_entry(&self, path: &str) -> Option<(usize, &Entry)> {
+        let re = self.entries.iter().enumerate().find(|(_, e)| e.path == path);
+        if re.is_none() {
+            warning!("{} not found in snapshot {}, skipped.", path, self.id);
+        }
+        re
+    }
+}
+
+pub trait Store {
+    /// Save all `paths` into a new snapshot labeled with `tags`.
+    fn backup(&self, paths: &[String], tags: &[String]) -> Result<Snapshot, SnapshotError>;
+    /// List all snapshots, oldest first.
+    fn list(&self) -> Result<Vec<Snapshot>, SnapshotError>;
+    /// Restore `paths` from `snapshot`, replacing the current content.
+    fn restore(&self, snapshot: &Snapshot, paths: &[String]) -> Result<(), SnapshotError>;
+    /// Delete snapshots and any data only they used.
+    fn remove(&self, snapshots: &[&Snapshot]) -> Result<(), SnapshotError>;
+
+    fn latest(&self) -> Result<Option<Snapshot>, SnapshotError> {
+        Ok(self.list()?.pop())
+    }
+}
+
+/// Open the snapshot store located at `dir`.
+pub fn open(dir: PathBuf, dedup: bool) -> Box<dyn Store> {
+    if dedup {
+        Box::new(dedup::DedupStore::new(dir))
+    } else {
+        Box::new(plain::PlainStore::new(dir))
+    }
+}
+
+/// Generate an unused snapshot id from the current time.
+fn new_id<F: Fn(&str) -> bool>(time: &DateTime<Local>, exists: F) -> String {
+    let base = time.format("%Y%m%d-%H%M%S").to_string();
+    let mut id = base.clone();
+    let mut n = 1;
+    while exists(&id) {
+        n += 1;
+        id = format!("{}-{}", base, n);
+    }
+    id
+}
+
+fn load_yaml(path: &Path) -> Result<Yaml, SnapshotError> {
+    let mut f = File::open(path)?;
+    let mut s = String::new();
+    f.read_to_string(&mut s)?;
+    let mut doc = YamlLoader::load_from_str(&s)?;
+    if doc.is_empty() {
+        return Err(SnapshotError::InvalidManifest(path.to_string_lossy().to_string()));
+    }
+    Ok(doc.swap_remove(0))
+}
+
+fn save_yaml(path: &Path, obj: Hash) -> Result<(), SnapshotError> {
+    let mut s = String::new();
+    YamlEmitter::new(&mut s).dump(&Yaml::Hash(obj))?;
+    s.push('\n');
+    let mut f = File::create(path)?;
+    f.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+/// Remove a file or a directory with its contents, if it exists.
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else if path.symlink_metadata().is_ok() {
+        fs::remove_file(path)
+    } else {
+        Ok(())
+    }
+}
+
+/// Let `fill` write the new content of `dest` next to it, and replace `dest` only if that succeeds,
+/// so a failed restore keeps the current saves.
+fn replace<F: FnOnce(&Path) -> std::io::Result<()>>(dest: &Path, fill: F) -> std::io::Result<()> {
+    let name = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp = dest.with_file_name(format!(".{}.restore.tmp", name));
+    let old = dest.with_file_name(format!(".{}.restore.old", name));
+    remove_path(&tmp)?;
+    remove_path(&old)?;
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if let Err(e) = fill(&tmp) {
+        let _ = remove_path(&tmp);
+        return Err(e);
+    }
+    // Move the current saves aside instead of deleting them, a file still in use could stop the
+    // deletion halfway.
+    let exists = dest.symlink_metadata().is_ok();
+    if exists {
+        fs::rename(dest, &old)?;
+    }
+    if let Err(e) = fs::rename(&tmp, dest) {
+        if exists {
+            fs::rename(&old, dest)?;
+        }
+        return Err(e);
+    }
+    if let Err(e) = remove_path(&old) {
+        warning!("Failed to remove {}: {}", old.display(), e);
+    }
+    Ok(())
+}
+
+/// Copy a file and keep its modification time.
+fn copy_file(src: &Path, dest: &Path) -> std::io::Result<()> {
+    fs::copy(src, dest)?;
+    let modified = fs::metadata(src)?.modified()?;
+    File::options().write(true).open(dest)?.set_modified(modified)
+}
@@ -0,0 +1,221 @@
+use super::{copy_file, load_yaml, new_id, replace, save_yaml, Entry, EntryKind, Snapshot, SnapshotError, Store};
+use crate::log::warning;
+use chrono::Local;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "snapshot.yml";
+
+/// Every snapshot is a full copy of the backup paths in its own folder.
+pub struct PlainStore {
+    dir: PathBuf,
+}
+
+impl PlainStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for e in fs::read_dir(src)? {
+        let e = e?;
+        let target = dest.join(e.file_name());
+        if e.file_type()?.is_dir() {
+            copy_dir(&e.path(), &target)?;
+        } else {
+            copy_file(&e.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Count the files under `dir` and their total size.
+fn dir_stats(dir: &Path) -> std::io::Result<(usize, u64)> {
+    let mut re = (0, 0);
+    for e in fs::read_dir(dir)? {
+        let e = e?;
+        let meta = e.metadata()?;
+        if meta.is_dir() {
+            let (files, size) = dir_stats(&e.path())?;
+            re.0 += files;
+            re.1 += size;
+        } else if e.file_name() != MANIFEST {
+            re.0 += 1;
+            re.1 += meta.len();
+        }
+    }
+    Ok(re)
+}
+
+/// Copy `src` to `dest` and return what kind of entry `src` was.
+fn copy_entry(src: &Path, dest: &Path) -> std::io::Result<EntryKind> {
+    if src.is_dir() {
+        copy_dir(src, dest)?;
+        Ok(EntryKind::Dir)
+    } else if src.exists() {
+        copy_file(src, dest)?;
+        Ok(EntryKind::File)
+    } else {
+        Ok(EntryKind::Missing)
+    }
+}
+
+impl Store for PlainStore {
+    fn backup(&self, paths: &[String], tags: &[String]) -> Result<Snapshot, SnapshotError> {
+        fs::create_dir_all(&self.dir)?;
+        let time = Local::now();
+        let id = new_id(&time, |id| self.dir.join(id).exists());
+        // Write into a hidden folder first so an interrupted backup never looks like a snapshot.
+        let tmp = self.dir.join(format!(".{}.tmp", id));
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)?;
+        }
+        fs::create_dir_all(&tmp)?;
+        let mut entries = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let kind = copy_entry(Path::new(path), &tmp.join(i.to_string()))?;
+            if kind == EntryKind::Missing {
+                warning!("{} not found, skipped.", path);
+            }
+            entries.push(Entry {
+                path: path.to_owned(),
+                kind,
+            });
+        }
+        let mut snapshot = Snapshot::new(id, time, entries, tags);
+        (snapshot.files, snapshot.size) = dir_stats(&tmp)?;
+        save_yaml(&tmp.join(MANIFEST), snapshot.to_hash())?;
+        fs::rename(&tmp, self.dir.join(&snapshot.id))?;
+        Ok(snapshot)
+    }
+
+    fn list(&self) -> Result<Vec<Snapshot>, SnapshotError> {
+        let mut re = Vec::new();
+        if !self.dir.is_dir() {
+            return Ok(re);
+        }
+        for e in fs::read_dir(&self.dir)? {
+            let e = e?;
+            let manifest = e.path().join(MANIFEST);
+            if e.file_name().to_string_lossy().starts_with('.') || !manifest.exists() {
+                continue;
+            }
+            let id = e.file_name().to_string_lossy().to_string();
+            let doc = load_yaml(&manifest)?;
+            let mut s = Snapshot::from_yaml(id, &doc)?;
+            // Manifests written by older versions have no stats.
+            if doc["file_count"].is_badvalue() {
+                (s.files, s.size) = dir_stats(&e.path())?;
+            }
+            re.push(s);
+        }
+        re.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.id.cmp(&b.id)));
+        Ok(re)
+    }
+
+    fn restore(&self, snapshot: &Snapshot, paths: &[String]) -> Result<(), SnapshotError> {
+        let base = self.dir.join(&snapshot.id);
+        for p in paths {
+            let (i, entry) = match snapshot.find_entry(p) {
+                Some(e) => e,
+                None => continue,
+            };
+            let src = base.join(i.to_string());
+            let dest = Path::new(p);
+            match entry.kind {
+                EntryKind::Missing => continue,
+                EntryKind::Dir => replace(dest, |tmp| copy_dir(&src, tmp))?,
+                EntryKind::File => replace(dest, |tmp| copy_file(&src, tmp))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, snapshots: &[&Snapshot]) -> Result<(), SnapshotError> {
+        for s in snapshots {
+            fs::remove_dir_all(self.dir.join(&s.id))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    /// A save folder and a single save file under `dir`.
+    fn saves(dir: &Path) -> Vec<String> {
+        let saves = dir.join("saves");
+        fs::create_dir_all(saves.join("sub")).unwrap();
+        fs::create_dir_all(saves.join("empty")).unwrap();
+        fs::write(saves.join("a.sav"), "a1").unwrap();
+        fs::write(saves.join("sub").join("b.sav"), "b1").unwrap();
+        fs::write(dir.join("config.ini"), "c1").unwrap();
+        vec![saves.to_string_lossy().to_string(), dir.join("config.ini").to_string_lossy().to_string()]
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = test_dir("plain-round-trip");
+        let paths = saves(&dir);
+        let store = PlainStore::new(dir.join("snapshots"));
+        let s = store.backup(&paths, &["pc".to_owned()]).unwrap();
+        assert_eq!((s.files, s.size), (3, 6));
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, s.id);
+        assert_eq!((listed[0].files, listed[0].size), (3, 6));
+        assert_eq!(listed[0].tags, ["pc"]);
+
+        let saves = dir.join("saves");
+        fs::write(saves.join("a.sav"), "a2").unwrap();
+        fs::write(saves.join("new.sav"), "n").unwrap();
+        fs::remove_dir_all(saves.join("sub")).unwrap();
+        fs::write(dir.join("config.ini"), "c2").unwrap();
+        store.restore(&listed[0], &paths).unwrap();
+        assert_eq!(fs::read_to_string(saves.join("a.sav")).unwrap(), "a1");
+        assert_eq!(fs::read_to_string(saves.join("sub").join("b.sav")).unwrap(), "b1");
+        assert!(saves.join("empty").is_dir());
+        assert!(!saves.join("new.sav").exists());
+        assert_eq!(fs::read_to_string(dir.join("config.ini")).unwrap(), "c1");
+        assert!(!dir.join(".saves.restore.tmp").exists());
+        assert!(!dir.join(".saves.restore.old").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restore_missing_entry() {
+        let dir = test_dir("plain-missing");
+        let paths = vec![dir.join("later.sav").to_string_lossy().to_string()];
+        let store = PlainStore::new(dir.join("snapshots"));
+        let s = store.backup(&paths, &[]).unwrap();
+        assert_eq!(s.entries[0].kind, EntryKind::Missing);
+        // A path which did not exist is left as it is.
+        fs::write(dir.join("later.sav"), "l").unwrap();
+        store.restore(&s, &paths).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("later.sav")).unwrap(), "l");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn list_without_stats() {
+        let dir = test_dir("plain-no-stats");
+        let paths = saves(&dir);
+        let store = PlainStore::new(dir.join("snapshots"));
+        let s = store.backup(&paths, &[]).unwrap();
+        // Manifests of older versions have no file count and size.
+        let manifest = dir.join("snapshots").join(&s.id).join(MANIFEST);
+        let doc = fs::read_to_string(&manifest).unwrap();
+        let doc: Vec<_> = doc
+            .lines()
+            .filter(|l| !l.starts_with("file_count") && !l.starts_with("size"))
+            .collect();
+        fs::write(&manifest, doc.join("\n")).unwrap();
+        let listed = store.list().unwrap();
+        assert_eq!((listed[0].files, listed[0].size), (3, 6));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use super::Snapshot;
+use chrono::Datelike;
+
+/// How many snapshots to keep. A snapshot is kept if any rule selects it.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    /// Keep the newest snapshots.
+    pub last: usize,
+    /// Keep the newest snapshot of each of the last N hours which have snapshots.
+    pub hourly: usize,
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+}
+
+impl Policy {
+    pub fn is_empty(&self) -> bool {
+        self.last == 0 && self.hourly == 0 && self.daily == 0 && self.weekly == 0 && self.monthly == 0
+    }
+}
+
+fn bucket(s: &Snapshot, rule: usize) -> String {
+    match rule {
+        0 => s.time.format("%Y%m%d%H").to_string(),
+        1 => s.time.format("%Y%m%d").to_string(),
+        2 => {
+            let w = s.time.iso_week();
+            format!("{}-{}", w.year(), w.week())
+        }
+        _ => s.time.format("%Y%m").to_string(),
+    }
+}
+
+/// Returns whether each snapshot should be kept. `snapshots` must be sorted oldest first.
+pub fn select(policy: &Policy, snapshots: &[Snapshot]) -> Vec<bool> {
+    let mut keep = vec![policy.is_empty(); snapshots.len()];
+    if policy.is_empty() {
+        return keep;
+    }
+    let mut last = policy.last;
+    let mut counts = [policy.hourly, policy.daily, policy.weekly, policy.monthly];
+    let mut last_keys: [Option<String>; 4] = Default::default();
+    for (i, s) in snapshots.iter().enumerate().rev() {
+        if last > 0 {
+            last -= 1;
+            keep[i] = true;
+        }
+        for rule in 0..counts.len() {
+            if counts[rule] == 0 {
+                continue;
+            }
+            let key = bucket(s, rule);
+            if last_keys[rule].as_ref() != Some(&key) {
+                counts[rule] -= 1;
+                keep[i] = true;
+                last_keys[rule] = Some(key);
+            }
+        }
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, NaiveDateTime, TimeZone};
+
+    fn snapshots(times: &[&str]) -> Vec<Snapshot> {
+        times
+            .iter()
+            .map(|t| {
+                let t = NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M").unwrap();
+                let t = Local.from_local_datetime(&t).unwrap();
+                Snapshot::new(t.format("%Y%m%d-%H%M%S").to_string(), t, Vec::new(), &[])
+            })
+            .collect()
+    }
+
+    fn kept(policy: &Policy, times: &[&str]) -> Vec<bool> {
+        select(policy, &snapshots(times))
+    }
+
+    #[test]
+    fn empty_policy_keeps_all() {
+        assert_eq!(kept(&Policy::default(), &["2026-01-01 10:00", "2026-01-01 11:00"]), [true, true]);
+        assert!(kept(&Policy::default(), &[]).is_empty());
+    }
+
+    #[test]
+    fn keep_last() {
+        let policy = Policy { last: 2, ..Default::default() };
+        let times = ["2026-01-01 10:00", "2026-01-01 10:01", "2026-01-01 10:02"];
+        assert_eq!(kept(&policy, &times), [false, true, true]);
+        let policy = Policy { last: 5, ..Default::default() };
+        assert_eq!(kept(&policy, &times), [true, true, true]);
+    }
+
+    #[test]
+    fn hourly_boundary() {
+        let policy = Policy { hourly: 2, ..Default::default() };
+        let times = ["2026-01-01 09:59", "2026-01-01 10:00", "2026-01-01 10:59", "2026-01-01 11:00"];
+        // The newest of each hour: 11:00 and 10:59, the hour of 09:59 is beyond the count.
+        assert_eq!(kept(&policy, &times), [false, false, true, true]);
+    }
+
+    #[test]
+    fn daily_boundary() {
+        let policy = Policy { daily: 3, ..Default::default() };
+        let times = ["2026-01-01 00:00", "2026-01-01 23:59", "2026-01-02 00:00", "2026-01-02 12:00"];
+        assert_eq!(kept(&policy, &times), [false, true, false, true]);
+    }
+
+    #[test]
+    fn weekly_boundary() {
+        let policy = Policy { weekly: 10, ..Default::default() };
+        // 2026-01-04 is a Sunday and 2026-01-05 a Monday, the start of an ISO week.
+        let times = ["2026-01-04 10:00", "2026-01-04 20:00", "2026-01-05 08:00"];
+        assert_eq!(kept(&policy, &times), [false, true, true]);
+        // 2020-12-31 and 2021-01-03 are both in week 53 of 2020.
+        let times = ["2020-12-31 10:00", "2021-01-03 10:00", "2021-01-04 10:00"];
+        assert_eq!(kept(&policy, &times), [false, true, true]);
+    }
+
+    #[test]
+    fn monthly_boundary() {
+        let policy = Policy { monthly: 2, ..Default::default() };
+        let times = ["2025-12-31 23:59", "2026-01-31 23:59", "2026-02-01 00:00"];
+        assert_eq!(kept(&policy, &times), [false, true, true]);
+    }
+
+    #[test]
+    fn overlapping_rules() {
+        let policy = Policy {
+            last: 1,
+            daily: 2,
+            monthly: 2,
+            ..Default::default()
+        };
+        let times = [
+            "2025-12-30 10:00",
+            "2025-12-31 10:00",
+            "2026-01-01 10:00",
+            "2026-01-02 09:00",
+            "2026-01-02 10:00",
+        ];
+        // last and daily both keep the newest one, which still uses a daily and a monthly slot.
+        // Daily keeps 01-02 and 01-01, monthly keeps 01-02 and 12-31.
+        assert_eq!(kept(&policy, &times), [false, true, true, false, true]);
+    }
+}
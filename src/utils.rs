@@ -1,8 +1,11 @@
+use crate::log::debug;
 use std::env;
-use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 /// Get executable location, if not found, return current directory (./)
 pub fn get_exe_path_else_current() -> PathBuf {
@@ -20,20 +23,251 @@ pub fn get_exe_path_else_current() -> PathBuf {
     }
 }
 
+/// Parse a duration like `90`, `30s`, `20m`, `1h30m` or `7d`. A bare number means seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<u64>() {
+        return Some(Duration::from_secs(n));
+    }
+    let mut total = 0u64;
+    let mut num = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total = num.parse::<u64>().ok()?.checked_mul(unit)?.checked_add(total)?;
+        num.clear();
+    }
+    if !num.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(total))
+}
+
+/// Find an executable by its path, or by its name in `PATH`.
+pub fn find_exe(name: &str) -> Option<PathBuf> {
+    let p = Path::new(name);
+    if p.is_absolute() || p.components().count() > 1 {
+        return if p.is_file() { Some(p.to_path_buf()) } else { None };
+    }
+    let exts: &[&str] = if cfg!(windows) { &["", ".exe", ".bat", ".cmd"] } else { &[""] };
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(|d| exts.iter().map(move |e| d.join(format!("{}{}", name, e))))
+        .find(|p| p.is_file())
+}
+
+/// Check that files can be created in `dir`, creating it if needed.
+pub fn check_writable(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let p = dir.join(".game-auto-sync-write-test");
+    std::fs::write(&p, b"")?;
+    std::fs::remove_file(&p)
+}
+
+/// Format a size in bytes like `512 B`, `1.5 KiB` or `20.0 MiB`.
+pub fn format_size(size: i64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut s = size as f64 / 1024.0;
+    let mut unit = 0;
+    while s >= 1024.0 && unit < UNITS.len() - 1 {
+        s /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", s, UNITS[unit])
+}
+
+/// Get the name of this machine, or `unknown` if it can not be detected.
+pub fn hostname() -> String {
+    hostname::get()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|_| String::from("unknown"))
+}
+
+/// Lines read from stdin by a background thread, so reading can time out.
+fn stdin_lines() -> &'static Mutex<Receiver<String>> {
+    static LINES: OnceLock<Mutex<Receiver<String>>> = OnceLock::new();
+    LINES.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            let mut d = String::new();
+            match std::io::stdin().read_line(&mut d) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if tx.send(d).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => continue,
+            }
+        });
+        Mutex::new(rx)
+    })
+}
+
+/// Read a line from stdin. Returns `None` on timeout or if stdin is closed.
+fn read_line(timeout: Option<Duration>) -> Option<String> {
+    let rx = stdin_lines().lock().unwrap();
+    match timeout {
+        Some(t) => rx.recv_timeout(t).ok(),
+        None => rx.recv().ok(),
+    }
+}
+
 pub fn ask_continue() -> bool {
     print!("Do you want to continue?(y/n)");
     std::io::stdout().flush().unwrap();
-    let mut d = String::from("");
+    let d = match read_line(None) {
+        Some(d) => d.trim().to_lowercase(),
+        // stdin is closed, nobody can answer.
+        None => {
+            println!();
+            String::new()
+        }
+    };
+    debug!("Asked to continue, answered {:?}", d);
+    if d == "y" {
+        crate::report::user_continued();
+    }
+    d == "y"
+}
+
+/// Like [ask_continue], but returns `default` if there is no answer in `timeout`.
+pub fn ask_continue_timeout(timeout: Duration, default: bool) -> bool {
+    print!(
+        "Do you want to continue?(y/n, {} in {}s)",
+        if default { "continue" } else { "abort" },
+        timeout.as_secs()
+    );
+    std::io::stdout().flush().unwrap();
+    match read_line(Some(timeout)) {
+        Some(d) => {
+            let d = d.trim().to_lowercase();
+            debug!("Asked to continue, answered {:?}", d);
+            if d == "y" {
+                crate::report::user_continued();
+            }
+            d == "y"
+        }
+        None => {
+            println!();
+            debug!("No answer in {}s, {}", timeout.as_secs(), if default { "continue" } else { "abort" });
+            default
+        }
+    }
+}
+
+/// Phases which have a failure policy.
+pub const FAILURE_PHASES: &[&str] = &["restore_rclone", "restore", "run_exe", "backup", "backup_rclone"];
+
+/// What to do when a phase fails.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailurePolicy {
+    Abort,
+    Continue,
+    Ask,
+    /// Ask, and use the second value as the answer if there is none in time.
+    AskWithTimeout(Duration, bool),
+}
+
+impl FailurePolicy {
+    /// Parse `abort`, `continue`, `ask` or `ask-with-timeout:<secs>=<abort|continue>`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "abort" => Some(Self::Abort),
+            "continue" => Some(Self::Continue),
+            "ask" => Some(Self::Ask),
+            _ => {
+                let (t, d) = s.strip_prefix("ask-with-timeout:")?.split_once('=')?;
+                let d = match d {
+                    "abort" => false,
+                    "continue" => true,
+                    _ => return None,
+                };
+                Some(Self::AskWithTimeout(parse_duration(t)?, d))
+            }
+        }
+    }
+
+    /// Whether to continue after a failure, may ask the user.
+    pub fn should_continue(&self) -> bool {
+        match self {
+            Self::Abort => false,
+            Self::Continue => true,
+            Self::Ask => ask_continue(),
+            Self::AskWithTimeout(t, d) => ask_continue_timeout(*t, *d),
+        }
+    }
+
+    /// Pick one of `choices` when something needs a decision: `safe` to continue, `None` to abort,
+    /// or ask the user.
+    pub fn choose(&self, choices: &[(&str, &str)], safe: usize) -> Option<usize> {
+        match self {
+            Self::Abort => None,
+            Self::Continue => Some(safe),
+            Self::Ask => self.answered(read_choice(choices, None), safe),
+            Self::AskWithTimeout(t, _) => self.answered(ask_choice_timeout(choices, *t), safe),
+        }
+    }
+
+    /// The choice for the `answer` read from the console, `None` if nobody answered.
+    fn answered(&self, answer: Option<usize>, safe: usize) -> Option<usize> {
+        match (answer, self) {
+            (Some(i), _) => Some(i),
+            (None, Self::AskWithTimeout(_, d)) => d.then_some(safe),
+            // No console to ask, never take a choice which may overwrite saves.
+            (None, _) => {
+                debug!("No console to ask, use the safe choice");
+                Some(safe)
+            }
+        }
+    }
+}
+
+/// Ask the user to pick one of `choices`, returns `None` if there is no answer within `timeout`.
+pub fn ask_choice_timeout(choices: &[(&str, &str)], timeout: Duration) -> Option<usize> {
+    let re = read_choice(choices, Some(Instant::now() + timeout));
+    if re.is_none() {
+        debug!("No answer in {}s", timeout.as_secs());
+    }
+    re
+}
+
+/// Ask the user to pick one of `choices`, each given as `(key, description)`.
+/// Returns the index of the chosen one, or `None` if stdin is closed or `deadline` passed.
+fn read_choice(choices: &[(&str, &str)], deadline: Option<Instant>) -> Option<usize> {
+    for (k, d) in choices {
+        println!("  [{}] {}", k, d);
+    }
+    let keys: Vec<_> = choices.iter().map(|(k, _)| *k).collect();
     loop {
-        let re = std::io::stdin().read_line(&mut d);
-        if re.is_err() {
-            continue;
+        let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        match timeout {
+            Some(t) => print!("Please choose({}, {:.0}s left):", keys.join("/"), t.as_secs_f64().ceil()),
+            None => print!("Please choose({}):", keys.join("/")),
         }
-        let d = d.trim().to_lowercase();
-        if d == "y" {
-            return true;
-        } else {
-            return false;
+        std::io::stdout().flush().unwrap();
+        let d = match read_line(timeout) {
+            Some(d) => d.trim().to_lowercase(),
+            None => {
+                println!();
+                return None;
+            }
+        };
+        if let Some(i) = keys.iter().position(|k| *k == d) {
+            debug!("Chose {}", choices[i].1);
+            return Some(i);
         }
     }
 }
@@ -41,8 +275,46 @@ pub fn ask_continue() -> bool {
 pub fn enter_continue() {
     print!("Press enter to continue.");
     std::io::stdout().flush().unwrap();
-    let mut f = [0u8; 1];
-    match std::io::stdin().read_exact(&mut f) {
-        _ => {}
+    read_line(None);
+}
+
+/// An empty folder for a test in the temp folder.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("game-auto-sync-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2w1d"), Some(Duration::from_secs(15 * 86400)));
+        assert_eq!(parse_duration("10"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("10x"), None);
+        assert_eq!(parse_duration("1h30"), None);
+        assert_eq!(parse_duration("h"), None);
+    }
+
+    #[test]
+    fn duration_overflow() {
+        assert_eq!(parse_duration("40000000000000w"), None);
+        assert_eq!(parse_duration("18446744073709551615s1s"), None);
+        assert_eq!(parse_duration("99999999999999999999d"), None);
+    }
+
+    #[test]
+    fn choice_without_console() {
+        assert_eq!(FailurePolicy::Ask.answered(None, 2), Some(2));
+        assert_eq!(FailurePolicy::Ask.answered(Some(0), 2), Some(0));
+        let timeout = Duration::from_secs(5);
+        assert_eq!(FailurePolicy::AskWithTimeout(timeout, true).answered(None, 1), Some(1));
+        assert_eq!(FailurePolicy::AskWithTimeout(timeout, false).answered(None, 1), None);
     }
 }
@@ -0,0 +1,44 @@
+use crate::log::warning;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+/// Messages for the background backup thread.
+pub enum Signal {
+    /// The game has exited.
+    Stop,
+    /// Files in a watched path were written or removed.
+    Changed,
+}
+
+/// Watch `paths` recursively and send [Signal::Changed] to `tx` when something in them changes.
+///
+/// Paths which can not be watched, e.g. because they do not exist yet, are reported and skipped.
+/// Returns `None` if nothing is watched. Watching stops when the returned watcher is dropped.
+pub fn watch(paths: &[String], tx: Sender<Signal>) -> Option<RecommendedWatcher> {
+    let mut watcher = match notify::recommended_watcher(move |e: notify::Result<notify::Event>| {
+        if let Ok(e) = e {
+            if matches!(e.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
+                let _ = tx.send(Signal::Changed);
+            }
+        }
+    }) {
+        Ok(w) => w,
+        Err(e) => {
+            warning!("Failed to create file watcher: {}.", e);
+            return None;
+        }
+    };
+    let mut watched = false;
+    for p in paths {
+        match watcher.watch(Path::new(p), RecursiveMode::Recursive) {
+            Ok(_) => watched = true,
+            Err(e) => warning!("Failed to watch {}: {}.", p, e),
+        }
+    }
+    if watched {
+        Some(watcher)
+    } else {
+        None
+    }
+}
@@ -1,34 +1,43 @@
 use std::ffi::{OsStr, OsString};
+use std::fs::File;
 use std::mem::size_of;
 use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
 use std::ptr::{addr_of_mut, null, null_mut};
+use crate::capture::Capture;
+use crate::log::warning;
 use winapi::ctypes::c_void;
 use winapi::shared::basetsd::ULONG_PTR;
 use winapi::shared::minwindef::DWORD;
 use winapi::um::errhandlingapi::GetLastError;
-use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::handleapi::{CloseHandle, SetHandleInformation, INVALID_HANDLE_VALUE};
 use winapi::um::ioapiset::{CreateIoCompletionPort, GetQueuedCompletionStatus};
 use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
 use winapi::um::jobapi2::{AssignProcessToJobObject, SetInformationJobObject};
 use winapi::um::memoryapi::{VirtualAllocEx, WriteProcessMemory, VirtualFreeEx};
-use winapi::um::minwinbase::LPOVERLAPPED;
+use winapi::um::minwinbase::{LPOVERLAPPED, SECURITY_ATTRIBUTES};
+use winapi::um::namedpipeapi::CreatePipe;
+use winapi::um::processenv::{GetStdHandle, SetStdHandle};
 use winapi::um::processthreadsapi::{
     CreateProcessW, GetExitCodeProcess, ResumeThread, TerminateProcess, PROCESS_INFORMATION,
     STARTUPINFOW, CreateRemoteThread,
 };
 use winapi::um::synchapi::WaitForSingleObject;
-use winapi::um::winbase::{CreateJobObjectA, CREATE_SUSPENDED, INFINITE};
+use winapi::um::winbase::{
+    CreateJobObjectA, CREATE_SUSPENDED, CREATE_UNICODE_ENVIRONMENT, HANDLE_FLAG_INHERIT, INFINITE, STARTF_USESTDHANDLES,
+    STD_ERROR_HANDLE, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE,
+};
 use winapi::um::wincon::GetConsoleWindow;
 use winapi::um::winnt::{
     JobObjectAssociateCompletionPortInformation, JOBOBJECT_ASSOCIATE_COMPLETION_PORT,
-    JOB_OBJECT_MSG_ACTIVE_PROCESS_ZERO, MEM_COMMIT, PAGE_READWRITE, MEM_RELEASE,
+    JOB_OBJECT_MSG_ACTIVE_PROCESS_ZERO, MEM_COMMIT, PAGE_READWRITE, MEM_RELEASE, HANDLE,
 };
 use winapi::um::winuser::{ShowWindow, SW_HIDE, SW_SHOW};
 
 fn console_show_window(n_cmd_show: i32) -> bool {
     let h_wnd = unsafe { GetConsoleWindow() };
     if h_wnd.is_null() {
-        println!("Failed to get console window.");
+        warning!("Failed to get console window.");
         return false;
     }
     unsafe { ShowWindow(h_wnd, n_cmd_show) != 0 }
@@ -42,24 +51,83 @@ pub fn hide_window() -> bool {
     console_show_window(SW_HIDE)
 }
 
+/// Send all output to stdout of this process and the commands it starts to stderr,
+/// and return the original stdout.
+pub fn take_stdout() -> std::io::Result<File> {
+    let out = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
+    let err = unsafe { GetStdHandle(STD_ERROR_HANDLE) };
+    if out.is_null() || out == INVALID_HANDLE_VALUE || err.is_null() || err == INVALID_HANDLE_VALUE {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no standard output"));
+    }
+    if unsafe { SetStdHandle(STD_OUTPUT_HANDLE, err) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_handle(out as _) })
+}
+
 #[derive(Debug, derive_more::Display, derive_more::From)]
 pub enum PopenError {
     CreateJobFailed,
     CreateProcessFailed,
     AssignJobFailed,
     CreateThreadFailed,
+    CreatePipeFailed,
 }
 
-pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls: &[T], cdir: Option<C>) -> Result<u32, PopenError> {
+/// A pipe whose write end can be inherited by child processes.
+fn inheritable_pipe() -> Result<(File, OwnedHandle), PopenError> {
+    let mut sa = SECURITY_ATTRIBUTES {
+        nLength: size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+        lpSecurityDescriptor: null_mut(),
+        bInheritHandle: 1,
+    };
+    let mut r: HANDLE = null_mut();
+    let mut w: HANDLE = null_mut();
+    if unsafe { CreatePipe(addr_of_mut!(r), addr_of_mut!(w), addr_of_mut!(sa), 0) } == 0 {
+        warning!("Failed to create pipe: {}.", unsafe { GetLastError() });
+        return Err(PopenError::CreatePipeFailed);
+    }
+    let (r, w) = unsafe { (File::from_raw_handle(r as _), OwnedHandle::from_raw_handle(w as _)) };
+    // Only the write end is for the child.
+    unsafe { SetHandleInformation(r.as_raw_handle() as HANDLE, HANDLE_FLAG_INHERIT, 0) };
+    Ok((r, w))
+}
+
+/// Environment block with the variables of this process and `env`, sorted by name as Windows requires.
+fn env_block(env: &[(String, String)]) -> Vec<u16> {
+    let mut vars: Vec<(OsString, OsString)> = std::env::vars_os()
+        .filter(|(k, _)| !env.iter().any(|(e, _)| OsStr::new(e).eq_ignore_ascii_case(k)))
+        .collect();
+    vars.extend(env.iter().map(|(k, v)| (OsString::from(k), OsString::from(v))));
+    vars.sort_by_key(|(k, _)| k.to_string_lossy().to_uppercase());
+    let mut block = Vec::new();
+    for (k, v) in vars {
+        block.extend(k.encode_wide());
+        block.push('=' as u16);
+        block.extend(v.encode_wide());
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
+
+/// Run `argv` and wait for all processes in its job. `env` is added to the environment of this process.
+///
+/// The output is copied to `capture` if given.
+pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls: &[T], cdir: Option<C>, env: &[(String, String)], capture: Option<&Capture>) -> Result<u32, PopenError> {
+    let pipes = match capture {
+        Some(_) => Some((inheritable_pipe()?, inheritable_pipe()?)),
+        None => None,
+    };
     let job = unsafe { CreateJobObjectA(null_mut(), null()) };
     if job.is_null() {
-        println!("Failed to create job: {}.", unsafe { GetLastError() });
+        warning!("Failed to create job: {}.", unsafe { GetLastError() });
         return Err(PopenError::CreateJobFailed);
     }
     let io_port = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, null_mut(), 0, 1) };
     if io_port.is_null() {
         unsafe { CloseHandle(job) };
-        println!("CreateIoCompletionPort: {}", unsafe { GetLastError() });
+        warning!("CreateIoCompletionPort: {}", unsafe { GetLastError() });
         return Err(PopenError::CreateJobFailed);
     }
     let mut port = JOBOBJECT_ASSOCIATE_COMPLETION_PORT::default();
@@ -76,10 +144,17 @@ pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls:
     if !ok {
         unsafe { CloseHandle(job) };
         unsafe { CloseHandle(io_port) };
-        println!("SetInformationJobObject: {}", unsafe { GetLastError() });
+        warning!("SetInformationJobObject: {}", unsafe { GetLastError() });
         return Err(PopenError::CreateJobFailed);
     }
     let mut si = STARTUPINFOW::default();
+    si.cb = size_of::<STARTUPINFOW>() as DWORD;
+    if let Some(((_, out), (_, err))) = &pipes {
+        si.dwFlags |= STARTF_USESTDHANDLES;
+        si.hStdInput = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+        si.hStdOutput = out.as_raw_handle() as HANDLE;
+        si.hStdError = err.as_raw_handle() as HANDLE;
+    }
     let mut pi = PROCESS_INFORMATION::default();
     let mut cml = OsString::new();
     for i in argv.iter() {
@@ -119,6 +194,12 @@ pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls:
         }
         None => null_mut(),
     };
+    let mut envw = if env.is_empty() { Vec::new() } else { env_block(env) };
+    let envp = if envw.is_empty() {
+        null_mut()
+    } else {
+        envw.as_mut_ptr() as *mut c_void
+    };
     let re = unsafe {
         CreateProcessW(
             null(),
@@ -126,22 +207,24 @@ pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls:
             null_mut(),
             null_mut(),
             1,
-            CREATE_SUSPENDED,
-            null_mut(),
+            CREATE_SUSPENDED | CREATE_UNICODE_ENVIRONMENT,
+            envp,
             cdir,
             addr_of_mut!(si),
             addr_of_mut!(pi),
         ) != 0
     };
+    // Close the write ends here, so the readers see the end when the child and its descendants exit.
+    let readers = pipes.map(|((out, _), (err, _))| (out, err));
     if !re {
-        println!("Failed to create process: {}.", unsafe { GetLastError() });
+        warning!("Failed to create process: {}.", unsafe { GetLastError() });
         unsafe { CloseHandle(job) };
         unsafe { CloseHandle(io_port) };
         return Err(PopenError::CreateProcessFailed);
     }
     let re = unsafe { AssignProcessToJobObject(job, pi.hProcess) != 0 };
     if !re {
-        println!("Failed to assign process to job.");
+        warning!("Failed to assign process to job.");
         unsafe { CloseHandle(job) };
         unsafe { CloseHandle(pi.hProcess) };
         unsafe { CloseHandle(pi.hThread) };
@@ -161,7 +244,7 @@ pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls:
             )
         };
         if p_dll_path.is_null() {
-            println!("Failed to allocate memory in remote process.");
+            warning!("Failed to allocate memory in remote process.");
             unsafe { TerminateProcess(pi.hProcess, 1) };
             unsafe { CloseHandle(job) };
             unsafe { CloseHandle(io_port) };
@@ -179,7 +262,7 @@ pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls:
             ) != 0
         };
         if !re {
-            println!("Failed to write memory in remote process.");
+            warning!("Failed to write memory in remote process.");
             unsafe { VirtualFreeEx(pi.hProcess, p_dll_path, 0, MEM_RELEASE) };
             unsafe { TerminateProcess(pi.hProcess, 1) };
             unsafe { CloseHandle(job) };
@@ -200,7 +283,7 @@ pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls:
             )
         };
         if h_thread.is_null() {
-            println!("Failed to create remote thread.");
+            warning!("Failed to create remote thread.");
             unsafe { VirtualFreeEx(pi.hProcess, p_dll_path, 0, MEM_RELEASE) };
             unsafe { TerminateProcess(pi.hProcess, 1) };
             unsafe { CloseHandle(job) };
@@ -213,6 +296,11 @@ pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls:
         unsafe { VirtualFreeEx(pi.hProcess, p_dll_path, 0, MEM_RELEASE) };
         unsafe { CloseHandle(h_thread) };
     }
+    let mut tee = Vec::new();
+    if let (Some(c), Some((out, err))) = (capture, readers) {
+        tee.push(c.tee(out, false));
+        tee.push(c.tee(err, true));
+    }
     unsafe { ResumeThread(pi.hThread) };
     let mut code = DWORD::default();
     let mut key = ULONG_PTR::default();
@@ -235,5 +323,8 @@ pub fn call<S: AsRef<OsStr>, T: AsRef<OsStr>, C: AsRef<OsStr>>(argv: &[S], dlls:
     unsafe { CloseHandle(io_port) };
     unsafe { CloseHandle(pi.hThread) };
     unsafe { CloseHandle(pi.hProcess) };
+    for t in tee {
+        let _ = t.join();
+    }
     Ok(c)
 }